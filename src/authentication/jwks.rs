@@ -0,0 +1,290 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use jsonwebtoken::{jwk::JwkSet, Algorithm};
+
+use super::VerificationKey;
+
+/// How often the key set file is checked for changes
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Keys read from a JWKS file, found by their `kid`. Rotating a key means
+/// adding the new one to the file, waiting for tokens signed by the old one
+/// to expire, then removing it. Both work in between.
+pub struct KeySet {
+    path: PathBuf,
+    /// Applied to every key in the file. Each key only gets the ones that suit it.
+    algorithms: Vec<Algorithm>,
+    keys: RwLock<HashMap<String, VerificationKey>>,
+    /// When the file was last changed as of the last time it was read
+    modified: Mutex<Option<SystemTime>>,
+}
+
+pub type SharedKeySet = Arc<KeySet>;
+
+impl KeySet {
+    /// Read the key set for the first time. Unlike a reload this fails if
+    /// the file can't be used.
+    pub fn load(path: impl Into<PathBuf>, algorithms: Vec<Algorithm>) -> Result<Self, String> {
+        let key_set = Self {
+            path: path.into(),
+            algorithms,
+            keys: RwLock::new(HashMap::new()),
+            modified: Mutex::new(None),
+        };
+        key_set.reload()?;
+        Ok(key_set)
+    }
+
+    pub fn get(&self, kid: &str) -> Option<VerificationKey> {
+        self.keys.read().unwrap().get(kid).cloned()
+    }
+
+    /// Read the file again and swap in its keys. If it can't be read or has no
+    /// usable keys, the ones already loaded are kept. Returns how many keys
+    /// were loaded.
+    pub fn reload(&self) -> Result<usize, String> {
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        let contents = std::fs::read_to_string(&self.path)
+            .map_err(|e| format!("Could not read {}: {e}", self.path.display()))?;
+        let jwks: JwkSet = serde_json::from_str(&contents)
+            .map_err(|e| format!("{} is not a JWKS: {e}", self.path.display()))?;
+
+        let mut keys = HashMap::new();
+        for jwk in jwks.keys {
+            let Some(kid) = jwk.common.key_id.clone() else {
+                warn!("Skipping a key in {} without a kid", self.path.display());
+                continue;
+            };
+            match VerificationKey::from_jwk(&jwk)
+                .and_then(|key| key.with_suitable_algorithms(&self.algorithms))
+            {
+                Ok(key) => {
+                    if keys.insert(kid.clone(), key).is_some() {
+                        warn!("{} has more than one key with kid [{kid}], using the last", self.path.display());
+                    }
+                }
+                Err(e) => warn!("Skipping key [{kid}] in {}: {e}", self.path.display()),
+            }
+        }
+
+        if keys.is_empty() {
+            return Err(format!("{} has no usable keys", self.path.display()));
+        }
+
+        let count = keys.len();
+        let mut kids: Vec<&String> = keys.keys().collect();
+        kids.sort();
+        info!("Loaded registration keys {kids:?} from {}", self.path.display());
+
+        *self.keys.write().unwrap() = keys;
+        *self.modified.lock().unwrap() = modified;
+        Ok(count)
+    }
+
+    /// Reload if the file has changed since it was last read
+    fn reload_if_changed(&self) {
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if modified == *self.modified.lock().unwrap() {
+            return;
+        }
+
+        if let Err(e) = self.reload() {
+            error!("Keeping the current registration keys: {e}");
+            // Don't try the same broken file again until it changes
+            *self.modified.lock().unwrap() = modified;
+        }
+    }
+}
+
+/// Reload the key set whenever its file changes or the process gets a SIGHUP
+pub fn spawn_reloader(key_set: SharedKeySet) {
+    tokio::task::spawn(async move {
+        #[cfg(unix)]
+        let mut hangup =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()).unwrap();
+        let mut timer = tokio::time::interval(RELOAD_CHECK_INTERVAL);
+
+        loop {
+            #[cfg(unix)]
+            tokio::select! {
+                _ = timer.tick() => key_set.reload_if_changed(),
+                _ = hangup.recv() => {
+                    info!("Got SIGHUP, reloading registration keys");
+                    if let Err(e) = key_set.reload() {
+                        error!("Keeping the current registration keys: {e}");
+                    }
+                }
+            }
+
+            #[cfg(not(unix))]
+            {
+                timer.tick().await;
+                key_set.reload_if_changed();
+            }
+        }
+    });
+}
+
+/// Every key a registration token can be signed with
+#[derive(Clone)]
+pub struct RegistrationKeys {
+    /// The single key from the configuration, for tokens without a kid or
+    /// with one the key set doesn't have
+    key: Option<VerificationKey>,
+    key_set: Option<SharedKeySet>,
+}
+
+impl RegistrationKeys {
+    pub fn new(key: Option<VerificationKey>, key_set: Option<SharedKeySet>) -> Self {
+        Self { key, key_set }
+    }
+
+    /// The key to check a token with the given kid against
+    pub fn find(&self, kid: Option<&str>) -> Option<VerificationKey> {
+        kid.and_then(|kid| self.key_set.as_ref()?.get(kid))
+            .or_else(|| self.key.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ED_KEY: &str = r#"{"kty": "OKP", "crv": "Ed25519", "kid": "ed", "x": "aoqSy9RR-3dm_nwHsZVVG7I3Wa8EBbkNcWBjU_iIp_Q"}"#;
+
+    const EC_KEY: &str = r#"{"kty": "EC", "crv": "P-256", "kid": "ec", "x": "pYDwCVZI5bUVgNOJR3lHTfO0oOQlR-PbqML-RLVwjgE", "y": "3GkSb4uEexir0oRTKeyk4_RTnbvxByazKBzQ0l1HNxQ"}"#;
+
+    /// A key set file that is removed again when the test ends
+    struct KeyFile(PathBuf);
+
+    impl KeyFile {
+        fn new(keys: &[&str]) -> Self {
+            let name = format!("jwks-{}.json", uuid::Uuid::new_v4());
+            let file = Self(std::env::temp_dir().join(name));
+            file.write(keys);
+            file
+        }
+
+        fn write(&self, keys: &[&str]) {
+            self.write_raw(&format!(r#"{{"keys": [{}]}}"#, keys.join(",")));
+        }
+
+        /// Write the file and move its modification time on, so the change
+        /// is noticed however coarse the file system's clock is
+        fn write_raw(&self, contents: &str) {
+            let modified = std::fs::metadata(&self.0)
+                .and_then(|metadata| metadata.modified())
+                .map(|modified| modified + Duration::from_secs(1))
+                .unwrap_or_else(|_| SystemTime::now());
+            std::fs::write(&self.0, contents).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&self.0)
+                .and_then(|file| file.set_modified(modified))
+                .unwrap();
+        }
+    }
+
+    impl Drop for KeyFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn keys_are_found_by_kid() {
+        let file = KeyFile::new(&[ED_KEY, EC_KEY]);
+        let key_set = KeySet::load(&file.0, Vec::new()).unwrap();
+
+        assert_eq!(key_set.get("ed").unwrap().algorithms(), [Algorithm::EdDSA]);
+        assert_eq!(key_set.get("ec").unwrap().algorithms(), [Algorithm::ES256]);
+        assert!(key_set.get("other").is_none());
+    }
+
+    #[test]
+    fn unusable_keys_are_skipped() {
+        let without_kid = r#"{"kty": "OKP", "crv": "Ed25519", "x": "aoqSy9RR-3dm_nwHsZVVG7I3Wa8EBbkNcWBjU_iIp_Q"}"#;
+        let symmetric = r#"{"kty": "oct", "kid": "oct", "k": "c2VjcmV0"}"#;
+        let file = KeyFile::new(&[ED_KEY, without_kid, symmetric]);
+        let key_set = KeySet::load(&file.0, Vec::new()).unwrap();
+
+        assert!(key_set.get("ed").is_some());
+        assert!(key_set.get("oct").is_none());
+        assert_eq!(key_set.keys.read().unwrap().len(), 1);
+
+        file.write(&[symmetric]);
+        assert!(KeySet::load(&file.0, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn keys_only_get_the_algorithms_that_suit_them() {
+        let file = KeyFile::new(&[ED_KEY, EC_KEY]);
+        let key_set = KeySet::load(&file.0, vec![Algorithm::ES256, Algorithm::RS256]).unwrap();
+
+        // Nothing suits the Ed25519 key so it is left out
+        assert!(key_set.get("ed").is_none());
+        assert_eq!(key_set.get("ec").unwrap().algorithms(), [Algorithm::ES256]);
+    }
+
+    #[test]
+    fn changes_to_the_file_are_picked_up() {
+        let file = KeyFile::new(&[ED_KEY]);
+        let key_set = KeySet::load(&file.0, Vec::new()).unwrap();
+
+        // Rotating in a new key
+        file.write(&[ED_KEY, EC_KEY]);
+        key_set.reload_if_changed();
+        assert!(key_set.get("ed").is_some());
+        assert!(key_set.get("ec").is_some());
+
+        // And the old one out
+        file.write(&[EC_KEY]);
+        key_set.reload_if_changed();
+        assert!(key_set.get("ed").is_none());
+        assert!(key_set.get("ec").is_some());
+    }
+
+    #[test]
+    fn a_broken_file_keeps_the_current_keys() {
+        let file = KeyFile::new(&[ED_KEY]);
+        let key_set = KeySet::load(&file.0, Vec::new()).unwrap();
+
+        file.write_raw("not a key set");
+        assert!(key_set.reload().is_err());
+        key_set.reload_if_changed();
+        assert!(key_set.get("ed").is_some());
+
+        file.write(&[]);
+        assert!(key_set.reload().is_err());
+        assert!(key_set.get("ed").is_some());
+
+        std::fs::remove_file(&file.0).unwrap();
+        assert!(key_set.reload().is_err());
+        assert!(key_set.get("ed").is_some());
+    }
+
+    #[test]
+    fn the_configured_key_is_used_for_unknown_kids() {
+        let file = KeyFile::new(&[EC_KEY]);
+        let key_set = Arc::new(KeySet::load(&file.0, Vec::new()).unwrap());
+        let fallback = VerificationKey::parse(ED_KEY).unwrap();
+
+        let keys = RegistrationKeys::new(Some(fallback), Some(key_set.clone()));
+        assert_eq!(keys.find(Some("ec")).unwrap().algorithms(), [Algorithm::ES256]);
+        assert_eq!(keys.find(Some("other")).unwrap().algorithms(), [Algorithm::EdDSA]);
+        assert_eq!(keys.find(None).unwrap().algorithms(), [Algorithm::EdDSA]);
+
+        let keys = RegistrationKeys::new(None, Some(key_set));
+        assert!(keys.find(Some("other")).is_none());
+        assert!(keys.find(None).is_none());
+    }
+}
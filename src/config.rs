@@ -1,15 +1,148 @@
-use jsonwebtoken::DecodingKey;
+use jsonwebtoken::Algorithm;
 use serde::{de::Error, Deserialize, Deserializer};
 
 use base64::engine::general_purpose::STANDARD as base64decoder;
 use base64::Engine;
 
+use crate::authentication::VerificationKey;
+
 #[derive(Clone, Deserialize)]
 pub struct Configuration {
     pub service_address: String,
     pub service_port: u16,
-    #[serde(deserialize_with = "deserialize_decoding_key")]
-    pub new_presentation_signing_key: DecodingKey,
+    /// An RSA, EC or Ed25519 public key as a PEM or JWK. Used for
+    /// registration tokens without a kid, or with one that isn't in
+    /// `new_presentation_signing_keys`.
+    #[serde(default, deserialize_with = "deserialize_verification_key")]
+    pub new_presentation_signing_key: Option<VerificationKey>,
+    /// A JWKS file of keys registration tokens can be signed with, picked by
+    /// the token's kid. It's reloaded when it changes or on SIGHUP.
+    #[serde(default)]
+    pub new_presentation_signing_keys: Option<String>,
+    /// Which algorithms registration tokens may be signed with. If this is
+    /// not set the usual one for each key is used.
+    #[serde(default)]
+    pub new_presentation_signing_algorithms: Vec<Algorithm>,
+    /// Where to save presentations so they survive a restart. If this is
+    /// not set presentations only live in memory.
+    #[serde(default)]
+    pub storage: Option<StorageConfiguration>,
+    /// Where to keep a log of every event in every presentation. If this
+    /// is not set nothing is journaled.
+    #[serde(default)]
+    pub journal: Option<JournalConfiguration>,
+    /// End presentations that nobody is connected to after this many seconds
+    /// without activity. If this is not set presentations live until the
+    /// presenter ends them.
+    #[serde(default)]
+    pub idle_timeout: Option<u64>,
+    /// Serve /metrics on this port instead of the public one so it can be
+    /// kept off the internet
+    #[serde(default)]
+    pub metrics_port: Option<u16>,
+    /// Serve /metrics on the public port when there's no `metrics_port`.
+    /// Off by default since metrics list every presentation.
+    #[serde(default)]
+    pub public_metrics: bool,
+    /// How long, in seconds, to wait for clients to disconnect after a
+    /// SIGTERM before exiting anyway
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+    /// What join tokens have to say about who issued them and who they're for
+    #[serde(default)]
+    pub join_tokens: JoinTokenConfiguration,
+    /// How websockets are checked for clients that have silently gone away
+    #[serde(default)]
+    pub heartbeat: HeartbeatConfiguration,
+    /// How many messages can be waiting to be sent to a single client. Once
+    /// it's full, reactions are dropped and anything else disconnects them.
+    #[serde(default = "default_client_queue_depth")]
+    pub client_queue_depth: usize,
+}
+
+fn default_client_queue_depth() -> usize {
+    512
+}
+
+fn default_shutdown_timeout() -> u64 {
+    10
+}
+
+#[derive(Clone, Deserialize)]
+pub struct JournalConfiguration {
+    /// The directory journals are written to
+    pub directory: String,
+}
+
+fn default_heartbeat_interval() -> u64 {
+    15
+}
+
+fn default_heartbeat_max_missed() -> u32 {
+    3
+}
+
+#[derive(Clone, Deserialize)]
+pub struct HeartbeatConfiguration {
+    /// How often, in seconds, every websocket is pinged
+    #[serde(default = "default_heartbeat_interval")]
+    pub interval: u64,
+    /// How many pings in a row can go unanswered before the connection is dropped
+    #[serde(default = "default_heartbeat_max_missed")]
+    pub max_missed: u32,
+}
+
+impl Default for HeartbeatConfiguration {
+    fn default() -> Self {
+        Self {
+            interval: default_heartbeat_interval(),
+            max_missed: default_heartbeat_max_missed(),
+        }
+    }
+}
+
+fn default_clock_skew() -> u64 {
+    60
+}
+
+#[derive(Clone, Deserialize)]
+pub struct JoinTokenConfiguration {
+    /// Only accept tokens with one of these `iss` claims. If this is empty
+    /// the issuer isn't checked.
+    #[serde(default)]
+    pub issuers: Vec<String>,
+    /// Only accept tokens with one of these in their `aud` claim. If this
+    /// is empty the audience isn't checked.
+    #[serde(default)]
+    pub audiences: Vec<String>,
+    /// How many seconds clocks may disagree by when checking `exp`, `nbf`
+    /// and `iat`
+    #[serde(default = "default_clock_skew")]
+    pub clock_skew: u64,
+}
+
+impl Default for JoinTokenConfiguration {
+    fn default() -> Self {
+        Self {
+            issuers: Vec::new(),
+            audiences: Vec::new(),
+            clock_skew: default_clock_skew(),
+        }
+    }
+}
+
+fn default_snapshot_interval() -> u64 {
+    30
+}
+
+#[derive(Clone, Deserialize)]
+pub struct StorageConfiguration {
+    /// The directory snapshots are written to. Reports of ended presentations
+    /// go in a `reports` directory inside it.
+    pub directory: String,
+    /// How often, in seconds, every presentation is saved. At least one.
+    #[serde(default = "default_snapshot_interval")]
+    pub snapshot_interval: u64,
 }
 
 /// Fetch the Exhibit configuration. Check a path if one is provided, otherwise
@@ -28,6 +161,17 @@ pub fn load_configuration() -> Configuration{
 
     let mut config: Configuration = toml::from_str(&configuration_toml).unwrap();
 
+    if config.new_presentation_signing_key.is_none() && config.new_presentation_signing_keys.is_none() {
+        panic!("Either new_presentation_signing_key or new_presentation_signing_keys must be set");
+    }
+
+    let algorithms = config.new_presentation_signing_algorithms.clone();
+    config.new_presentation_signing_key = config
+        .new_presentation_signing_key
+        .map(|key| key.with_algorithms(&algorithms))
+        .transpose()
+        .unwrap_or_else(|e| panic!("Invalid new_presentation_signing_algorithms: {e}"));
+
     if let Ok(port) = std::env::var("PORT") {
         config.service_port = port.parse().unwrap();
     }
@@ -35,10 +179,10 @@ pub fn load_configuration() -> Configuration{
     config
 }
 
-fn deserialize_decoding_key<'de, D>(deserializer: D) -> Result<DecodingKey, D::Error>
+fn deserialize_verification_key<'de, D>(deserializer: D) -> Result<Option<VerificationKey>, D::Error>
 where
     D: Deserializer<'de>,
 {
     let s: String = Deserialize::deserialize(deserializer)?;
-    DecodingKey::from_ec_pem(s.as_bytes()).map_err(D::Error::custom)
+    VerificationKey::parse(&s).map(Some).map_err(D::Error::custom)
 }
@@ -0,0 +1,163 @@
+//! Encrypted presentations never show the server what anyone wrote. Clients
+//! share a key out of band, usually in the fragment of the join link which
+//! browsers never send, and seal every piece of content before sending it:
+//!
+//! `v1.<nonce>.<ciphertext>`
+//!
+//! Both parts are unpadded URL safe base64. The nonce is 12 bytes and the
+//! ciphertext is AES-256-GCM output including its 16 byte tag. The server
+//! only checks that content has this shape and relays it untouched.
+//!
+//! Emojis, prompt names, vote types, durations and every other setting stay
+//! in plaintext because the server has to act on them. Polls have no separate
+//! question so their names are sealed, and the sealed name is then the poll's
+//! id for votes and presenter commands.
+//!
+//! Votes and quiz answers must repeat sealed options exactly as the presenter
+//! sent them, so tallies, runoffs and quizzes work on the sealed strings and
+//! only clients with the key can read the results. Word clouds need to read
+//! responses so encrypted prompts don't have one. Length limits on responses
+//! and questions count bytes of plaintext since characters can't be counted.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+use crate::{IncomingPresenterMessage, IncomingUserMessage, NewPollMessage, NewPromptMessage};
+
+const SEALED_VERSION: &str = "v1";
+const NONCE_LENGTH: usize = 12;
+const TAG_LENGTH: usize = 16;
+/// Longest sealed string accepted, in characters
+const MAX_SEALED_LENGTH: usize = 4096;
+
+/// Check that `text` is sealed content and return how many bytes of
+/// plaintext it holds. `field` names what was being checked for the error.
+pub fn check_sealed(field: &str, text: &str) -> Result<usize, String> {
+    let invalid = || format!("{field} must be encrypted in an encrypted presentation");
+
+    if text.len() > MAX_SEALED_LENGTH {
+        return Err(format!("{field} is too long"));
+    }
+
+    let mut parts = text.split('.');
+    let (Some(SEALED_VERSION), Some(nonce), Some(ciphertext), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+
+    let nonce = URL_SAFE_NO_PAD.decode(nonce).map_err(|_| invalid())?;
+    let ciphertext = URL_SAFE_NO_PAD.decode(ciphertext).map_err(|_| invalid())?;
+    if nonce.len() != NONCE_LENGTH || ciphertext.len() < TAG_LENGTH {
+        return Err(invalid());
+    }
+
+    Ok(ciphertext.len() - TAG_LENGTH)
+}
+
+fn check_poll(poll: &NewPollMessage) -> Result<(), String> {
+    check_sealed("Poll names", &poll.name)?;
+    for option in &poll.options {
+        check_sealed("Poll options", option)?;
+    }
+    Ok(())
+}
+
+fn check_prompt(prompt: &NewPromptMessage) -> Result<(), String> {
+    check_sealed("Prompt questions", &prompt.question).map(|_| ())
+}
+
+/// Check that everything a presenter wrote in a message is sealed
+pub fn check_presenter_message(message: &IncomingPresenterMessage) -> Result<(), String> {
+    match message {
+        IncomingPresenterMessage::NewSlide(msg) => {
+            check_sealed("Slide messages", &msg.slide_settings.message).map(|_| ())
+        }
+        IncomingPresenterMessage::NewPoll(poll) => check_poll(poll),
+        IncomingPresenterMessage::NewPrompt(prompt) => check_prompt(prompt),
+        _ => Ok(()),
+    }
+}
+
+/// Check that everything a user wrote in a message is sealed
+pub fn check_user_message(message: &IncomingUserMessage) -> Result<(), String> {
+    match message {
+        IncomingUserMessage::TextResponse(response) => {
+            check_sealed("Responses", &response.text).map(|_| ())
+        }
+        IncomingUserMessage::Question(question) => {
+            check_sealed("Questions", &question.text).map(|_| ())
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Seal `length` bytes of "plaintext" the way a client would lay it out
+    fn sealed(nonce_length: usize, length: usize) -> String {
+        format!(
+            "v1.{}.{}",
+            URL_SAFE_NO_PAD.encode(vec![1; nonce_length]),
+            URL_SAFE_NO_PAD.encode(vec![2; length + TAG_LENGTH])
+        )
+    }
+
+    #[test]
+    fn sealed_content_is_accepted() {
+        assert_eq!(check_sealed("Responses", &sealed(NONCE_LENGTH, 5)), Ok(5));
+        assert_eq!(check_sealed("Responses", &sealed(NONCE_LENGTH, 0)), Ok(0));
+    }
+
+    #[test]
+    fn plaintext_is_refused() {
+        assert!(check_sealed("Responses", "hello").is_err());
+        assert!(check_sealed("Responses", "").is_err());
+        assert!(check_sealed("Responses", "v1.hello.world").is_err());
+    }
+
+    #[test]
+    fn malformed_sealed_content_is_refused() {
+        let good = sealed(NONCE_LENGTH, 5);
+        let (_, rest) = good.split_once('.').unwrap();
+
+        assert!(check_sealed("Responses", &format!("v2.{rest}")).is_err());
+        assert!(check_sealed("Responses", &format!("{good}.extra")).is_err());
+        assert!(check_sealed("Responses", &sealed(NONCE_LENGTH - 1, 5)).is_err());
+        assert!(check_sealed("Responses", &sealed(NONCE_LENGTH + 1, 5)).is_err());
+
+        // Too short to hold the tag
+        let nonce = URL_SAFE_NO_PAD.encode([1; NONCE_LENGTH]);
+        let short = URL_SAFE_NO_PAD.encode([2; TAG_LENGTH - 1]);
+        assert!(check_sealed("Responses", &format!("v1.{nonce}.{short}")).is_err());
+
+        // Only unpadded URL safe base64
+        assert!(check_sealed("Responses", &format!("{good}=")).is_err());
+        assert!(check_sealed("Responses", &good.replace('Q', "+")).is_err());
+    }
+
+    #[test]
+    fn long_content_is_refused() {
+        assert_eq!(
+            check_sealed("Responses", &sealed(NONCE_LENGTH, MAX_SEALED_LENGTH)),
+            Err("Responses is too long".to_string())
+        );
+    }
+
+    #[test]
+    fn only_written_content_has_to_be_sealed() {
+        let message = |json: &str| serde_json::from_str::<IncomingUserMessage>(json).unwrap();
+        let text = sealed(NONCE_LENGTH, 5);
+
+        assert!(check_user_message(&message(&format!(
+            r#"{{"TextResponse": {{"prompt_name": "feedback", "text": "{text}"}}}}"#
+        )))
+        .is_ok());
+        assert!(check_user_message(&message(
+            r#"{"TextResponse": {"prompt_name": "feedback", "text": "hello"}}"#
+        ))
+        .is_err());
+        assert!(check_user_message(&message(r#"{"Question": {"text": "hello?"}}"#)).is_err());
+    }
+}
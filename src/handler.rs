@@ -1,8 +1,16 @@
 use crate::{
+    authentication::ReportRequest,
+    config::HeartbeatConfiguration,
+    health::SharedHealth,
+    journal::{JournalEntry, SharedJournal},
+    lifecycle,
+    metrics::{self, JoinRejection},
+    report::{EngagementReport, ReportFormat, Reports},
+    storage::{self, SharedStorage},
     ws, ClientJoinPresentationData,
     Presentation, Presentations, Presenter, User,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use warp::{http::StatusCode, reply::json, Reply, reject::Rejection};
 
 
@@ -13,17 +21,34 @@ pub struct RegisterResponse {
     url: String,
 }
 
+/// Turn away new joins while the server drains so clients retry against
+/// the instance replacing it
+fn shutting_down() -> warp::reply::Response {
+    warp::reply::with_header(
+        warp::reply::with_status("The server is shutting down", StatusCode::SERVICE_UNAVAILABLE),
+        "retry-after",
+        "5",
+    )
+    .into_response()
+}
+
 pub async fn join_handler(
     user_auth_data: ClientJoinPresentationData,
     presentations: Presentations,
-) -> Result<impl Reply> {
+    health: SharedHealth,
+) -> Result<warp::reply::Response> {
     debug!("Got joining call");
 
+    if health.is_shutting_down() {
+        return Ok(shutting_down());
+    }
+
     let presentation = presentations
         .get(&user_auth_data.presentation)
         .ok_or(warp::reject::not_found())?;
 
     let presentation = presentation.value();
+    presentation.touch();
     let presentation_id = &presentation.id;
     let identity = user_auth_data.claims.sub.as_str();
 
@@ -51,7 +76,57 @@ pub async fn join_handler(
 
     Ok(json(&RegisterResponse {
         url: format!("/ws/{presentation_id}/{guid}"),
-    }))
+    })
+    .into_response())
+}
+
+#[derive(Deserialize)]
+pub struct ResumeRequest {
+    /// The token from `InitialPresentationData`
+    token: String,
+    /// The sequence number of the last update the client saw
+    seq: u64,
+}
+
+/// Come back as the same user after a dropped connection, without the JWT
+pub async fn resume_handler(
+    presentation_id: String,
+    request: ResumeRequest,
+    presentations: Presentations,
+    health: SharedHealth,
+) -> Result<warp::reply::Response> {
+    if health.is_shutting_down() {
+        return Ok(shutting_down());
+    }
+
+    let presentation = presentations
+        .get(&presentation_id)
+        .ok_or(warp::reject::not_found())?;
+    let presentation = presentation.value();
+
+    let identity = match presentation.get_resume_tokens().redeem(&request.token) {
+        Some(identity) => identity,
+        None => {
+            warn!("Got a resume token for [{presentation_id}] that isn't valid");
+            metrics::join_rejected(JoinRejection::BadResumeToken, Some(&presentation_id));
+            return Err(warp::reject::not_found());
+        }
+    };
+    presentation.touch();
+
+    let mut user = User::new(identity, presentation_id.clone());
+    user.resume_from = Some(request.seq);
+    let guid = user.guid.clone();
+    info!(
+        "{} is resuming from update {} in [{presentation_id}] with guid [{guid}]",
+        user.identity, request.seq
+    );
+    presentation.users.insert(user);
+
+    Ok(json(&RegisterResponse {
+        url: format!("/ws/{presentation_id}/{guid}"),
+    })
+    .into_response())
 }
 
 pub async fn ws_handler(
@@ -59,8 +134,15 @@ pub async fn ws_handler(
     guid: String,
     ws: warp::ws::Ws,
     presentations: Presentations,
-) -> Result<impl Reply> {
+    health: SharedHealth,
+    heartbeat: HeartbeatConfiguration,
+    queue_depth: usize,
+) -> Result<warp::reply::Response> {
     trace!("Got websocket call for presentation: {presentation_id}!");
+    if health.is_shutting_down() {
+        return Ok(shutting_down());
+    }
+
     let presentation = presentations
         .get(&presentation_id)
         .ok_or(warp::reject::not_found())?;
@@ -87,20 +169,136 @@ pub async fn ws_handler(
                 socket,
                 presentation,
                 guid,
+                heartbeat,
+                queue_depth,
             )
-        }))
+        })
+        .into_response())
+}
+
+fn is_presenter(auth_data: &ClientJoinPresentationData, presentations: &Presentations) -> bool {
+    presentations
+        .get(&auth_data.presentation)
+        .map(|x| x.presenter_identity == auth_data.claims.sub)
+        .unwrap_or(false)
+}
+
+/// Build an engagement report for a presentation in the requested format.
+/// Only the presenter's token is accepted. Once the presentation has ended
+/// the report kept when it did is sent instead.
+pub async fn report_handler(
+    format: String,
+    request: ReportRequest,
+    presentations: Presentations,
+) -> Result<impl Reply> {
+    let format: ReportFormat = format.parse().map_err(|e| {
+        warn!("{e}");
+        warp::reject::not_found()
+    })?;
+
+    let auth_data = request.auth_data;
+    let report = match request.archived {
+        Some(archived) => {
+            if archived.presenter_identity != auth_data.claims.sub {
+                warn!(
+                    "{} requested the report for ended presentation [{}] but was not its presenter",
+                    auth_data.claims.sub, auth_data.presentation
+                );
+                return Err(warp::reject::not_found());
+            }
+            archived.report
+        }
+        None => {
+            if !is_presenter(&auth_data, &presentations) {
+                warn!(
+                    "{} requested a report for [{}] but is not its presenter",
+                    auth_data.claims.sub, auth_data.presentation
+                );
+                return Err(warp::reject::not_found());
+            }
+
+            // Clone the presentation out so the map isn't locked while the report is built
+            let presentation = presentations
+                .get(&auth_data.presentation)
+                .map(|x| x.value().clone())
+                .ok_or(warp::reject::not_found())?;
+            EngagementReport::new(&presentation)
+        }
+    };
+
+    Ok(warp::reply::with_header(
+        report.render(format),
+        "content-type",
+        format.content_type(),
+    ))
 }
 
-pub async fn health_handler() -> Result<impl Reply> {
+/// End a presentation. Only the presenter's token is accepted.
+pub async fn end_handler(
+    auth_data: ClientJoinPresentationData,
+    presentations: Presentations,
+    storage: Option<SharedStorage>,
+    reports: Reports,
+) -> Result<impl Reply> {
+    if !is_presenter(&auth_data, &presentations) {
+        warn!(
+            "{} tried to end [{}] but is not its presenter",
+            auth_data.claims.sub, auth_data.presentation
+        );
+        return Err(warp::reject::not_found());
+    }
+
+    if !lifecycle::end_presentation(
+        &auth_data.presentation,
+        &presentations,
+        storage.as_deref(),
+        &reports,
+        "The presenter ended the presentation",
+    ) {
+        return Err(warp::reject::not_found());
+    }
+
     Ok(StatusCode::OK)
 }
 
+pub async fn metrics_handler(presentations: Presentations) -> Result<impl Reply> {
+    Ok(warp::reply::with_header(
+        metrics::render(&presentations),
+        "content-type",
+        "text/plain; version=0.0.4",
+    ))
+}
+
+/// The process is up and able to answer requests
+pub async fn live_handler(health: SharedHealth, presentations: Presentations) -> Result<impl Reply> {
+    Ok(json(&health.liveness(&presentations)))
+}
+
+/// The node can take traffic: everything it depends on is usable and it
+/// isn't shutting down
+pub async fn ready_handler(health: SharedHealth, presentations: Presentations) -> Result<impl Reply> {
+    let readiness = health.readiness(&presentations).await;
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Ok(warp::reply::with_status(json(&readiness), status))
+}
+
 pub async fn new_presentation_hander(
-    presentation: Presentation,
+    mut presentation: Presentation,
     presentations: Presentations,
-) -> Result<impl Reply> {
+    storage: Option<SharedStorage>,
+    journal: Option<SharedJournal>,
+    health: SharedHealth,
+) -> Result<warp::reply::Response> {
     debug!("Registering presentation {}", presentation.id);
 
+    if health.is_shutting_down() {
+        return Ok(shutting_down());
+    }
+
     if presentations.get(&presentation.id).is_some() {
         error!(
             "Refusing to register a new version of presentation: {}",
@@ -109,7 +307,24 @@ pub async fn new_presentation_hander(
         return Err(warp::reject::reject());
     }
 
+    // Save new presentations right away rather than waiting for the next snapshot
+    if let Some(storage) = storage {
+        storage::save_presentation(&storage, &presentation).await;
+    }
+
+    presentation.journal = journal;
+    presentation.record(
+        &presentation.presenter_identity,
+        JournalEntry::Created {
+            presenter_identity: presentation.presenter_identity.clone(),
+            encrypted: presentation.encrypted,
+            authentication_key_source: presentation.authentication_key_source.clone(),
+            authentication_algorithms: presentation.authentication_algorithms.clone(),
+            title: presentation.get_title(),
+        },
+    );
+
     presentations.insert(presentation.id.clone(), presentation);
 
-    Ok(StatusCode::OK)
+    Ok(StatusCode::OK.into_response())
 }
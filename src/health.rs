@@ -0,0 +1,182 @@
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::{journal::SharedJournal, storage::SharedStorage, Presentations};
+
+/// Files the single page apps are served from. Without them nobody can join.
+const WEBROOT_FILES: &[&str] = &[
+    "webroot/join.html",
+    "webroot/present.html",
+    "webroot/new.html",
+    "webroot/icons/favicon.ico",
+];
+
+/// How long the results of checking the disk are reused for. Readiness is
+/// polled often and every check writes a file.
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+pub type SharedHealth = Arc<Health>;
+
+/// The checks that have to touch the disk
+#[derive(Clone)]
+struct DiskChecks {
+    webroot: Result<(), String>,
+    storage: Option<Result<(), String>>,
+    journal: Option<Result<(), String>>,
+}
+
+impl DiskChecks {
+    fn run(storage: Option<SharedStorage>, journal: Option<SharedJournal>) -> Self {
+        let missing: Vec<&str> = WEBROOT_FILES
+            .iter()
+            .copied()
+            .filter(|file| !Path::new(file).is_file())
+            .collect();
+        let webroot = if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Missing {}", missing.join(", ")))
+        };
+
+        Self {
+            webroot,
+            storage: storage.map(|x| x.check()),
+            journal: journal.map(|x| x.check()),
+        }
+    }
+}
+
+/// What the health checks need to know about the server
+pub struct Health {
+    started: Instant,
+    shutting_down: AtomicBool,
+    storage: Option<SharedStorage>,
+    journal: Option<SharedJournal>,
+    /// The last disk checks and when they were run
+    disk_checks: Mutex<Option<(Instant, DiskChecks)>>,
+}
+
+/// The result of checking one dependency
+#[derive(Serialize)]
+pub struct Check {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl From<Result<(), String>> for Check {
+    fn from(result: Result<(), String>) -> Self {
+        Self {
+            ok: result.is_ok(),
+            error: result.err(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Liveness {
+    pub uptime_seconds: u64,
+    pub presentations: usize,
+    pub users: usize,
+    pub presenters: usize,
+}
+
+#[derive(Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub shutting_down: bool,
+    #[serde(flatten)]
+    pub liveness: Liveness,
+    pub webroot: Check,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage: Option<Check>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub journal: Option<Check>,
+}
+
+impl Health {
+    pub fn new(storage: Option<SharedStorage>, journal: Option<SharedJournal>) -> Self {
+        Self {
+            started: Instant::now(),
+            shutting_down: AtomicBool::new(false),
+            storage,
+            journal,
+            disk_checks: Mutex::new(None),
+        }
+    }
+
+    /// Fail readiness from now on so load balancers stop sending traffic
+    pub fn shutting_down(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    pub fn liveness(&self, presentations: &Presentations) -> Liveness {
+        let mut users = 0;
+        let mut presenters = 0;
+        for presentation in presentations.iter() {
+            users += presentation.connected_users();
+            presenters += presentation.connected_presenters();
+        }
+
+        Liveness {
+            uptime_seconds: self.started.elapsed().as_secs(),
+            presentations: presentations.len(),
+            users,
+            presenters,
+        }
+    }
+
+    /// The disk checks, run again on a blocking thread if the last ones are
+    /// more than `DISK_CHECK_INTERVAL` old
+    async fn disk_checks(&self) -> DiskChecks {
+        if let Some((checked_at, ref checks)) = *self.disk_checks.lock().unwrap() {
+            if checked_at.elapsed() < DISK_CHECK_INTERVAL {
+                return checks.clone();
+            }
+        }
+
+        let storage = self.storage.clone();
+        let journal = self.journal.clone();
+        let checks = tokio::task::spawn_blocking(move || DiskChecks::run(storage, journal))
+            .await
+            .unwrap_or_else(|e| DiskChecks {
+                webroot: Err(e.to_string()),
+                storage: None,
+                journal: None,
+            });
+        *self.disk_checks.lock().unwrap() = Some((Instant::now(), checks.clone()));
+        checks
+    }
+
+    pub async fn readiness(&self, presentations: &Presentations) -> Readiness {
+        let checks = self.disk_checks().await;
+        let webroot = Check::from(checks.webroot);
+        let storage = checks.storage.map(Check::from);
+        let journal = checks.journal.map(Check::from);
+        let shutting_down = self.is_shutting_down();
+
+        Readiness {
+            ready: !shutting_down
+                && webroot.ok
+                && storage.as_ref().map(|x| x.ok).unwrap_or(true)
+                && journal.as_ref().map(|x| x.ok).unwrap_or(true),
+            shutting_down,
+            liveness: self.liveness(presentations),
+            webroot,
+            storage,
+            journal,
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use serde::Serialize;
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+use warp::ws::Message;
+
+use crate::{
+    config::HeartbeatConfiguration,
+    presentation::now_millis,
+    Delivery, OutgoingMessage, Presentation,
+};
+
+/// Round trip times measured with websocket pings. Shared by every copy of a
+/// client so the connection's task can update it and anyone can read it.
+#[derive(Debug, Default)]
+pub struct Latency {
+    /// The last round trip in milliseconds. Zero until the first pong arrives.
+    rtt: AtomicU64,
+}
+
+impl Latency {
+    /// The last round trip in milliseconds, if one has been measured
+    pub fn rtt(&self) -> Option<u64> {
+        match self.rtt.load(Ordering::Relaxed) {
+            0 => None,
+            rtt => Some(rtt),
+        }
+    }
+
+    fn record(&self, rtt: u64) {
+        // Keep zero to mean nothing has been measured
+        self.rtt.store(rtt.max(1), Ordering::Relaxed);
+    }
+}
+
+/// Pings a single connection and notices when it stops answering
+pub struct Heartbeat {
+    timer: Interval,
+    max_missed: u32,
+    missed: u32,
+    awaiting_pong: bool,
+}
+
+impl Heartbeat {
+    pub fn new(configuration: &HeartbeatConfiguration) -> Self {
+        let interval = Duration::from_secs(configuration.interval.max(1));
+        // The first ping goes out one interval after the connection opens
+        let mut timer = tokio::time::interval_at(Instant::now() + interval, interval);
+        timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        Self {
+            timer,
+            max_missed: configuration.max_missed.max(1),
+            missed: 0,
+            awaiting_pong: false,
+        }
+    }
+
+    pub async fn tick(&mut self) {
+        self.timer.tick().await;
+    }
+
+    /// Send the next ping. Returns false if the connection has missed too many
+    /// and should be dropped.
+    pub fn beat<T: OutgoingMessage>(&mut self, client: &crate::Client<T>) -> bool {
+        if self.awaiting_pong {
+            self.missed += 1;
+            if self.missed >= self.max_missed {
+                return false;
+            }
+        }
+
+        // The payload is when the ping was sent so the pong says how long it took
+        client.queue(
+            Message::ping(now_millis().to_be_bytes().to_vec()),
+            Delivery::Droppable,
+        );
+        self.awaiting_pong = true;
+        true
+    }
+
+    /// Handle a pong, recording the round trip if it answers one of our pings
+    pub fn pong<T: OutgoingMessage>(&mut self, client: &crate::Client<T>, payload: &[u8]) {
+        self.awaiting_pong = false;
+        self.missed = 0;
+
+        if let Ok(sent) = <[u8; 8]>::try_from(payload) {
+            let rtt = now_millis().saturating_sub(u64::from_be_bytes(sent));
+            client.latency.record(rtt);
+        }
+    }
+}
+
+/// How well everyone in a presentation is connected, for the presenter
+#[derive(Clone, Debug, Serialize)]
+pub struct ConnectionQuality {
+    /// Round trip to the presenter asking, in milliseconds
+    pub presenter_rtt: Option<u64>,
+    /// Users whose round trip has been measured
+    pub measured_users: usize,
+    pub median_rtt: Option<u64>,
+    /// 90% of users have a round trip at or below this
+    pub p90_rtt: Option<u64>,
+    pub max_rtt: Option<u64>,
+}
+
+impl ConnectionQuality {
+    pub fn measure(presentation: &Presentation, presenter_guid: &str) -> Self {
+        let mut rtts: Vec<u64> = presentation
+            .users
+            .iter()
+            .filter(|user| user.sender.is_some())
+            .filter_map(|user| user.latency.rtt())
+            .collect();
+        rtts.sort_unstable();
+
+        let percentile = |p: usize| {
+            (!rtts.is_empty()).then(|| rtts[((rtts.len() - 1) * p).div_ceil(100)])
+        };
+
+        Self {
+            presenter_rtt: presentation
+                .presenters
+                .get(presenter_guid)
+                .and_then(|presenter| presenter.latency.rtt()),
+            measured_users: rtts.len(),
+            median_rtt: percentile(50),
+            p90_rtt: percentile(90),
+            max_rtt: rtts.last().copied(),
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+};
+
+use super::{Journal, JournalEvent};
+use crate::storage::file::check_writable;
+
+/// The most journal files the writer keeps open at once
+const MAX_OPEN_FILES: usize = 256;
+
+/// Work for the thread that writes the journal files
+enum Command {
+    Append { presentation_id: String, line: Vec<u8> },
+    /// Write out everything buffered so far, then answer
+    Flush(mpsc::Sender<Result<(), String>>),
+}
+
+/// Stores each presentation's journal as a file of JSON lines in a directory.
+/// Appends are handed to a single writer thread that keeps the files open,
+/// so recording an event never waits on the disk.
+pub struct FileJournal {
+    directory: PathBuf,
+    writer: mpsc::Sender<Command>,
+}
+
+impl FileJournal {
+    pub fn new(directory: impl Into<PathBuf>) -> Result<Self, String> {
+        let directory = directory.into();
+        std::fs::create_dir_all(&directory).map_err(|e| {
+            format!(
+                "Could not create journal directory {}: {e}",
+                directory.display()
+            )
+        })?;
+
+        let (writer, commands) = mpsc::channel();
+        let files = OpenFiles::new(directory.clone());
+        std::thread::Builder::new()
+            .name("journal-writer".to_string())
+            .spawn(move || files.run(commands))
+            .map_err(|e| format!("Could not start the journal writer: {e}"))?;
+
+        Ok(Self { directory, writer })
+    }
+
+    fn path(&self, presentation_id: &str) -> PathBuf {
+        journal_path(&self.directory, presentation_id)
+    }
+}
+
+/// Presentation ids come from JWTs so they are hex encoded to keep
+/// them inside the directory
+fn journal_path(directory: &Path, presentation_id: &str) -> PathBuf {
+    let name: String = presentation_id
+        .bytes()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    directory.join(format!("{name}.jsonl"))
+}
+
+/// The writer thread's open journal files
+struct OpenFiles {
+    directory: PathBuf,
+    files: HashMap<String, BufWriter<File>>,
+}
+
+impl OpenFiles {
+    fn new(directory: PathBuf) -> Self {
+        Self {
+            directory,
+            files: HashMap::new(),
+        }
+    }
+
+    /// Handle commands until every `FileJournal` is gone. Whatever is
+    /// buffered is written out whenever the queue runs dry, so a burst of
+    /// events goes to disk together and a quiet one goes straight away.
+    fn run(mut self, commands: mpsc::Receiver<Command>) {
+        while let Ok(command) = commands.recv() {
+            let mut next = Some(command);
+            while let Some(command) = next {
+                match command {
+                    Command::Append { presentation_id, line } => {
+                        if let Err(e) = self.append(&presentation_id, &line) {
+                            error!("Could not write to the journal for [{presentation_id}]: {e}");
+                        }
+                    }
+                    Command::Flush(done) => {
+                        let _ = done.send(self.flush());
+                    }
+                }
+                next = commands.try_recv().ok();
+            }
+
+            if let Err(e) = self.flush() {
+                error!("Could not write out the journal: {e}");
+            }
+        }
+    }
+
+    fn append(&mut self, presentation_id: &str, line: &[u8]) -> Result<(), String> {
+        if !self.files.contains_key(presentation_id) && self.files.len() >= MAX_OPEN_FILES {
+            // Make room by closing everything, busy presentations will open theirs again
+            self.flush()?;
+            self.files.clear();
+        }
+
+        let file = match self.files.entry(presentation_id.to_string()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(journal_path(&self.directory, presentation_id))
+                    .map_err(|e| e.to_string())?;
+                entry.insert(BufWriter::new(file))
+            }
+        };
+
+        if let Err(e) = file.write_all(line) {
+            // Open it again next time in case the file was the problem
+            self.files.remove(presentation_id);
+            return Err(e.to_string());
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        let mut result = Ok(());
+        self.files.retain(|presentation_id, file| match file.flush() {
+            Ok(()) => true,
+            Err(e) => {
+                result = Err(format!("[{presentation_id}]: {e}"));
+                false
+            }
+        });
+        result
+    }
+}
+
+fn decode_name(name: &str) -> Option<String> {
+    if !name.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes = (0..name.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(name.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+impl Journal for FileJournal {
+    fn append(&self, presentation_id: &str, event: &JournalEvent) -> Result<(), String> {
+        let mut line = serde_json::to_vec(event).map_err(|e| e.to_string())?;
+        line.push(b'\n');
+
+        self.writer
+            .send(Command::Append {
+                presentation_id: presentation_id.to_string(),
+                line,
+            })
+            .map_err(|_| "The journal writer has stopped".to_string())
+    }
+
+    fn flush(&self) -> Result<(), String> {
+        let (done, result) = mpsc::channel();
+        self.writer
+            .send(Command::Flush(done))
+            .map_err(|_| "The journal writer has stopped".to_string())?;
+        result
+            .recv()
+            .map_err(|_| "The journal writer has stopped".to_string())?
+    }
+
+    fn read(&self, presentation_id: &str) -> Result<Vec<JournalEvent>, String> {
+        // Include anything that is still buffered
+        if let Err(e) = self.flush() {
+            warn!("Reading the journal for [{presentation_id}] without the latest events: {e}");
+        }
+
+        let file = match std::fs::File::open(self.path(presentation_id)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.to_string()),
+        };
+
+        let mut events = vec![];
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            if line.is_empty() {
+                continue;
+            }
+            // A crash can leave a partial last line behind, skip it rather than
+            // losing the rest of the journal
+            match serde_json::from_str(&line) {
+                Ok(event) => events.push(event),
+                Err(e) => warn!(
+                    "Skipping unreadable line {} in the journal for [{presentation_id}]: {e}",
+                    number + 1
+                ),
+            }
+        }
+        Ok(events)
+    }
+
+    fn presentations(&self) -> Result<Vec<String>, String> {
+        let entries = std::fs::read_dir(&self.directory).map_err(|e| e.to_string())?;
+
+        let mut ids = vec![];
+        for entry in entries {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            if path.extension().and_then(|x| x.to_str()) != Some("jsonl") {
+                continue;
+            }
+            match path.file_stem().and_then(|x| x.to_str()).and_then(decode_name) {
+                Some(id) => ids.push(id),
+                None => warn!("Ignoring unexpected journal file {}", path.display()),
+            }
+        }
+        Ok(ids)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        check_writable(&self.directory)
+    }
+}
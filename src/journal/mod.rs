@@ -0,0 +1,461 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use jsonwebtoken::Algorithm;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    presentation::{now_millis, replaying_at},
+    processor, IncomingPresenterMessage, IncomingUserMessage,
+    OutgoingPresenterMessage, Presentation, Presentations, Presenter, User,
+};
+
+pub mod file;
+
+pub use file::FileJournal;
+
+pub type SharedJournal = Arc<dyn Journal>;
+
+/// Something that happened in a presentation
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum JournalEntry {
+    /// Always the first entry in a presentation's journal
+    Created {
+        presenter_identity: String,
+        encrypted: bool,
+        authentication_key_source: String,
+        authentication_algorithms: Vec<Algorithm>,
+        title: String,
+    },
+    User(IncomingUserMessage),
+    Presenter(IncomingPresenterMessage),
+    /// The presentation was ended. If its id is reused, the new
+    /// presentation starts with another `Created` entry.
+    Ended,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct JournalEvent {
+    /// Milliseconds since the epoch
+    pub timestamp: u64,
+    /// Who sent the message
+    pub identity: String,
+    pub entry: JournalEntry,
+}
+
+impl JournalEvent {
+    pub fn new(identity: &str, entry: JournalEntry) -> Self {
+        Self {
+            timestamp: now_millis(),
+            identity: identity.to_string(),
+            entry,
+        }
+    }
+}
+
+/// An append-only log of everything accepted by each presentation
+pub trait Journal: Send + Sync {
+    /// Add an event to the end of a presentation's journal. It may be
+    /// buffered for a while, `flush` makes sure it has been written.
+    fn append(&self, presentation_id: &str, event: &JournalEvent) -> Result<(), String>;
+
+    /// Wait until every event appended so far has been written
+    fn flush(&self) -> Result<(), String>;
+
+    /// Every event in a presentation's journal, oldest first
+    fn read(&self, presentation_id: &str) -> Result<Vec<JournalEvent>, String>;
+
+    /// The ids of every presentation that has a journal
+    fn presentations(&self) -> Result<Vec<String>, String>;
+
+    /// Make sure events can still be written
+    fn check(&self) -> Result<(), String>;
+}
+
+impl Presentation {
+    /// Add an event to this presentation's journal, if it has one. Failures
+    /// are logged rather than returned so a full disk doesn't stop the talk.
+    pub fn record(&self, identity: &str, entry: JournalEntry) {
+        if let Some(ref journal) = self.journal {
+            if let Err(e) = journal.append(&self.id, &JournalEvent::new(identity, entry)) {
+                error!("Could not write to the journal for [{}]: {e}", self.id);
+            }
+        }
+    }
+}
+
+/// Rebuild a presentation by running every event in its journal through the
+/// processor again. Nobody is connected to the rebuilt presentation so nothing
+/// is sent anywhere. Returns `None` if the presentation has been ended.
+///
+/// Each event is replayed with the clock set to when it was recorded, so poll
+/// deadlines and quiz speed bonuses come out the same as they did originally.
+pub async fn rebuild(
+    presentation_id: &str,
+    events: Vec<JournalEvent>,
+) -> Result<Option<Presentation>, String> {
+    let mut presentation: Option<Presentation> = None;
+    for event in current_session(events) {
+        presentation = replaying_at(
+            event.timestamp,
+            replay(presentation_id, event, presentation),
+        )
+        .await?;
+    }
+
+    // Like one restored from a snapshot, give it the full idle timeout from now
+    if let Some(ref presentation) = presentation {
+        presentation.touch();
+    }
+    Ok(presentation)
+}
+
+/// Apply one journal event to the presentation being rebuilt
+async fn replay(
+    presentation_id: &str,
+    event: JournalEvent,
+    presentation: Option<Presentation>,
+) -> Result<Option<Presentation>, String> {
+    match (event.entry, presentation) {
+        (
+            JournalEntry::Created {
+                presenter_identity,
+                encrypted,
+                authentication_key_source,
+                authentication_algorithms,
+                title,
+            },
+            _,
+        ) => Presentation::new(
+            presentation_id.to_string(),
+            presenter_identity,
+            encrypted,
+            authentication_key_source,
+            authentication_algorithms,
+            title,
+        )
+        .map(Some),
+        (JournalEntry::Ended, _) => Ok(None),
+        (JournalEntry::User(message), Some(presentation)) => {
+            let user = User::new(event.identity, presentation_id.to_string());
+            processor::dispatch_user_message(message, user, &presentation).await;
+            Ok(Some(presentation))
+        }
+        (JournalEntry::Presenter(message), Some(presentation)) => {
+            let presenter = Presenter::new(event.identity, presentation_id.to_string());
+            processor::handle_presenter_message_types(message, presenter, presentation.clone())
+                .await;
+            Ok(Some(presentation))
+        }
+        (_, None) => Err(format!(
+            "The journal for [{presentation_id}] does not start with its creation"
+        )),
+    }
+}
+
+/// The events since the presentation was last created. Earlier events
+/// belong to a presentation that used the same id and was ended.
+pub fn current_session(mut events: Vec<JournalEvent>) -> Vec<JournalEvent> {
+    let start = events
+        .iter()
+        .rposition(|event| matches!(event.entry, JournalEntry::Created { .. }))
+        .unwrap_or(0);
+    events.split_off(start)
+}
+
+/// Replay journal events from after a presentation's snapshot was taken onto
+/// the presentation restored from it. Returns false if the presentation was
+/// ended or created again after the snapshot, so it has to be rebuilt from
+/// the journal instead.
+async fn catch_up(
+    presentation: &Presentation,
+    events: Vec<JournalEvent>,
+    taken_at: u64,
+) -> Result<bool, String> {
+    let newer: Vec<JournalEvent> = events
+        .into_iter()
+        .filter(|event| event.timestamp >= taken_at)
+        .collect();
+    if newer
+        .iter()
+        .any(|event| matches!(event.entry, JournalEntry::Created { .. } | JournalEntry::Ended))
+    {
+        return Ok(false);
+    }
+
+    for event in newer {
+        replaying_at(
+            event.timestamp,
+            replay(&presentation.id, event, Some(presentation.clone())),
+        )
+        .await?;
+    }
+    presentation.touch();
+    Ok(true)
+}
+
+/// Bring every journaled presentation up to date, then give all presentations
+/// the journal so they keep recording. Presentations restored from a snapshot
+/// have the events since `snapshots_taken_at` replayed onto them, anything
+/// else is rebuilt from its whole journal.
+///
+/// Events handled while a snapshot was being taken may already be in it and
+/// are replayed again. Most are harmless twice, since votes and responses
+/// can't be repeated and slides just replace each other.
+pub async fn restore_presentations(
+    journal: SharedJournal,
+    presentations: &Presentations,
+    snapshots_taken_at: &HashMap<String, u64>,
+) {
+    let ids = match journal.presentations() {
+        Ok(ids) => ids,
+        Err(e) => {
+            error!("Could not list journaled presentations: {e}");
+            vec![]
+        }
+    };
+
+    for id in ids {
+        let events = match journal.read(&id) {
+            Ok(events) => events,
+            Err(e) => {
+                error!("Could not read the journal for [{id}]: {e}");
+                continue;
+            }
+        };
+
+        let restored = presentations.get(&id).map(|x| x.value().clone());
+        if let (Some(presentation), Some(&taken_at)) = (restored, snapshots_taken_at.get(&id)) {
+            match catch_up(&presentation, events.clone(), taken_at).await {
+                Ok(true) => {
+                    info!("Caught presentation [{id}] up with its journal");
+                    continue;
+                }
+                Ok(false) => {
+                    presentations.remove(&id);
+                }
+                Err(e) => {
+                    error!("Could not catch presentation [{id}] up with its journal: {e}");
+                    continue;
+                }
+            }
+        } else if presentations.contains_key(&id) {
+            continue;
+        }
+
+        match rebuild(&id, events).await {
+            Ok(Some(presentation)) => {
+                info!("Rebuilt presentation [{id}] from its journal");
+                presentations.insert(id, presentation);
+            }
+            Ok(None) => debug!("Not rebuilding presentation [{id}] because it was ended"),
+            Err(e) => error!("Could not rebuild presentation [{id}] from its journal: {e}"),
+        }
+    }
+
+    for mut presentation in presentations.iter_mut() {
+        presentation.journal = Some(journal.clone());
+    }
+}
+
+/// Send a presentation's journal to a presenter, keeping the original gaps
+/// between events divided by `speed`
+pub fn spawn_replay(events: Vec<JournalEvent>, presenter: Presenter, speed: f64) {
+    tokio::task::spawn(async move {
+        let mut previous = events.first().map(|event| event.timestamp).unwrap_or(0);
+        for event in events {
+            let gap = event.timestamp.saturating_sub(previous);
+            previous = event.timestamp;
+            if gap > 0 {
+                tokio::time::sleep(Duration::from_secs_f64(gap as f64 / 1000.0 / speed)).await;
+            }
+
+            // Wait for the presenter to keep up rather than flooding them, and
+            // stop early if they've gone away
+            let message = OutgoingPresenterMessage::ReplayEvent(event).to_sendable_message();
+            if !presenter.queue_waiting(message).await {
+                return;
+            }
+        }
+        presenter.send_ignore_fail(OutgoingPresenterMessage::ReplayFinished);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "-----BEGIN PUBLIC KEY-----
+MCowBQYDK2VwAyEAaoqSy9RR+3dm/nwHsZVVG7I3Wa8EBbkNcWBjU/iIp/Q=
+-----END PUBLIC KEY-----";
+
+    fn event(timestamp: u64, identity: &str, entry: JournalEntry) -> JournalEvent {
+        JournalEvent {
+            timestamp,
+            identity: identity.to_string(),
+            entry,
+        }
+    }
+
+    fn created(timestamp: u64, title: &str) -> JournalEvent {
+        event(
+            timestamp,
+            "presenter",
+            JournalEntry::Created {
+                presenter_identity: "presenter".to_string(),
+                encrypted: false,
+                authentication_key_source: KEY.to_string(),
+                authentication_algorithms: Vec::new(),
+                title: title.to_string(),
+            },
+        )
+    }
+
+    fn presenter(timestamp: u64, message: serde_json::Value) -> JournalEvent {
+        let message = serde_json::from_value(message).unwrap();
+        event(timestamp, "presenter", JournalEntry::Presenter(message))
+    }
+
+    fn vote(timestamp: u64, identity: &str, choice: &str) -> JournalEvent {
+        let message = serde_json::from_value(serde_json::json!({
+            "Vote": {"poll_name": "poll", "vote_type": {"SingleBinary": {"choice": choice}}}
+        }))
+        .unwrap();
+        event(timestamp, identity, JournalEntry::User(message))
+    }
+
+    /// A presentation with a poll and two votes in it
+    fn session(start: u64) -> Vec<JournalEvent> {
+        vec![
+            created(start, "Talk"),
+            presenter(
+                start + 1,
+                serde_json::json!({"NewPoll": {
+                    "name": "poll",
+                    "options": ["a", "b"],
+                    "vote_type": {"SingleBinary": {"choice": ""}},
+                }}),
+            ),
+            vote(start + 2, "first", "a"),
+            vote(start + 3, "second", "b"),
+        ]
+    }
+
+    fn close_poll(timestamp: u64) -> JournalEvent {
+        presenter(timestamp, serde_json::json!({"ClosePoll": {"name": "poll"}}))
+    }
+
+    /// A journal that only holds one presentation's events
+    struct MemoryJournal(Vec<JournalEvent>);
+
+    impl Journal for MemoryJournal {
+        fn append(&self, _: &str, _: &JournalEvent) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn flush(&self) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn read(&self, _: &str) -> Result<Vec<JournalEvent>, String> {
+            Ok(self.0.clone())
+        }
+
+        fn presentations(&self) -> Result<Vec<String>, String> {
+            Ok(vec!["presentation".to_string()])
+        }
+
+        fn check(&self) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    fn voters(presentation: &Presentation) -> u64 {
+        presentation.get_polls().get_poll("poll").unwrap().voters()
+    }
+
+    #[tokio::test]
+    async fn presentations_are_rebuilt_from_their_events() {
+        let mut events = session(1000);
+        events.push(close_poll(1004));
+
+        let presentation = rebuild("presentation", events).await.unwrap().unwrap();
+        assert_eq!(presentation.get_title(), "Talk");
+        assert_eq!(voters(&presentation), 2);
+        assert!(!presentation.get_polls().get_poll("poll").unwrap().is_open());
+    }
+
+    #[tokio::test]
+    async fn only_the_latest_session_is_rebuilt() {
+        let mut events = session(1000);
+        events.push(event(1004, "presenter", JournalEntry::Ended));
+        events.push(created(2000, "Another talk"));
+
+        let presentation = rebuild("presentation", events).await.unwrap().unwrap();
+        assert_eq!(presentation.get_title(), "Another talk");
+        assert!(presentation.get_polls().get_poll("poll").is_none());
+    }
+
+    #[tokio::test]
+    async fn ended_presentations_are_not_rebuilt() {
+        let mut events = session(1000);
+        events.push(event(1004, "presenter", JournalEntry::Ended));
+
+        assert!(rebuild("presentation", events).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn journals_must_start_with_a_creation() {
+        let events = session(1000).split_off(1);
+        assert!(rebuild("presentation", events).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn restored_presentations_catch_up_on_newer_events() {
+        let mut events = session(1000);
+        // As if the snapshot was taken after the first vote
+        let presentation = rebuild("presentation", events[..3].to_vec())
+            .await
+            .unwrap()
+            .unwrap();
+        events.push(close_poll(1004));
+
+        assert!(catch_up(&presentation, events, 1003).await.unwrap());
+        assert_eq!(voters(&presentation), 2);
+        assert!(!presentation.get_polls().get_poll("poll").unwrap().is_open());
+    }
+
+    #[tokio::test]
+    async fn presentations_ended_since_their_snapshot_are_not_caught_up() {
+        let mut events = session(1000);
+        let presentation = rebuild("presentation", events.clone()).await.unwrap().unwrap();
+        events.push(event(1004, "presenter", JournalEntry::Ended));
+        events.push(created(1005, "Another talk"));
+
+        assert!(!catch_up(&presentation, events, 1004).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn restoring_uses_the_snapshot_and_the_journal() {
+        let events = session(1000);
+        let journal: SharedJournal = Arc::new(MemoryJournal(events.clone()));
+
+        // Nothing was restored from a snapshot, so it is rebuilt
+        let presentations = Presentations::default();
+        restore_presentations(journal.clone(), &presentations, &HashMap::new()).await;
+        assert_eq!(voters(&presentations.get("presentation").unwrap()), 2);
+
+        // A snapshot from before the second vote is caught up
+        let presentations = Presentations::default();
+        let mut snapshot = events[..3].to_vec();
+        snapshot[0] = created(1000, "Snapshot");
+        let snapshot = rebuild("presentation", snapshot).await.unwrap().unwrap();
+        presentations.insert("presentation".to_string(), snapshot);
+        let taken_at = HashMap::from([("presentation".to_string(), 1003)]);
+        restore_presentations(journal, &presentations, &taken_at).await;
+
+        let presentation = presentations.get("presentation").unwrap();
+        assert_eq!(presentation.get_title(), "Snapshot");
+        assert_eq!(voters(&presentation), 2);
+        assert!(presentation.journal.is_some());
+    }
+}
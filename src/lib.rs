@@ -3,24 +3,59 @@ extern crate log;
 
 pub mod authentication;
 pub mod config;
+pub mod encryption;
 pub mod handler;
+pub mod health;
+pub mod heartbeat;
+pub mod journal;
+pub mod lifecycle;
 pub mod messaging;
+pub mod metrics;
 pub mod processor;
 pub mod presentation;
 pub mod ratelimiting;
+pub mod report;
+pub mod storage;
 pub mod ws;
 
 use std::sync::Arc;
 
+use heartbeat::Latency;
+
 pub use presentation::{Presentation, Vote, VoteType};
 pub use messaging::*;
 
-use dashmap::{DashMap, mapref::multiple::RefMulti};
+use dashmap::{DashMap, mapref::multiple::{RefMulti, RefMutMulti}};
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc;
+use tokio::sync::mpsc::{self, error::TrySendError};
 use uuid::Uuid;
 use warp::filters::ws::Message;
 
+/// Why a client that stopped reading its messages was disconnected
+const SLOW_CLIENT_REASON: &str = "Your connection couldn't keep up with the presentation. Reconnect to catch up.";
+
+/// Why a connection is being closed, handed to its task through `closer`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Disconnection {
+    pub reason: String,
+    /// Whether the client should connect again, like after a restart, rather
+    /// than the presentation being over for them
+    pub reconnect: bool,
+    /// Set when the client is disconnected because its queue filled up
+    pub evicted: bool,
+}
+
+impl Disconnection {
+    pub fn new(reason: impl Into<String>, reconnect: bool) -> Self {
+        Self { reason: reason.into(), reconnect, evicted: false }
+    }
+
+    /// For a client that fell too far behind. It can reconnect to catch up.
+    pub fn evicted() -> Self {
+        Self { evicted: true, ..Self::new(SLOW_CLIENT_REASON, true) }
+    }
+}
+
 pub type User = Client<OutgoingUserMessage>;
 pub type Presenter = Client<OutgoingPresenterMessage>;
 pub type Presenters = Arc<DashMap<String, Presenter>>;
@@ -28,11 +63,19 @@ pub type Presentations = Arc<DashMap<String, Presentation>>;
 
 #[derive(Debug, Clone)]
 pub struct Client<T> where T: OutgoingMessage {
-    pub sender: Option<mpsc::UnboundedSender<std::result::Result<Message, warp::Error>>>,
-    pub closer: Option<mpsc::UnboundedSender<()>>,
+    /// Queues messages for the websocket. Bounded so a client that can't keep
+    /// up can't use more and more memory.
+    pub sender: Option<mpsc::Sender<Message>>,
+    /// Tells the connection to close, with the reason given to the client
+    pub closer: Option<mpsc::UnboundedSender<Disconnection>>,
     pub identity: String,
     pub guid: String,
     pub presentation: String,
+    /// The last update a resuming client saw. Whatever it missed is sent
+    /// once its socket is open.
+    pub resume_from: Option<u64>,
+    /// Round trip times from pinging the connection
+    pub latency: Arc<Latency>,
     _phantom: std::marker::PhantomData<T>,
 }
 
@@ -44,22 +87,26 @@ impl Client<OutgoingPresenterMessage> {
             identity,
             guid: Uuid::new_v4().as_simple().to_string(),
             presentation,
+            resume_from: None,
+            latency: Arc::default(),
             _phantom: std::marker::PhantomData,
         }
     }
 
     pub fn close(&mut self) {
+        self.disconnect(Disconnection::default());
+    }
+
+    pub fn disconnect(&mut self, disconnection: Disconnection) {
         if let Some(sender) = self.closer.clone() {
-            let _ = sender.send(());
+            let _ = sender.send(disconnection);
             self.sender = None;
             self.closer = None;
         }
     }
 
     pub fn send_ignore_fail(&self, message: OutgoingPresenterMessage) {
-        if let Some(ref sender) = self.sender {
-            let _ = sender.send(Ok(Message::text(message.json())));
-        }
+        self.queue(Message::text(message.json()), message.delivery());
     }
 }
 
@@ -71,22 +118,76 @@ impl Client<OutgoingUserMessage> {
             identity,
             guid: Uuid::new_v4().as_simple().to_string(),
             presentation,
+            resume_from: None,
+            latency: Arc::default(),
             _phantom: std::marker::PhantomData,
         }
     }
 
     pub fn close(&mut self) {
+        self.disconnect(Disconnection::default());
+    }
+
+    pub fn disconnect(&mut self, disconnection: Disconnection) {
         if let Some(sender) = self.closer.clone() {
-            let _ = sender.send(());
+            let _ = sender.send(disconnection);
             self.sender = None;
             self.closer = None;
         }
     }
 
     pub fn send_ignore_fail(&self, message: OutgoingUserMessage) {
-        if let Some(ref sender) = self.sender {
-            let _ = sender.send(Ok(Message::text(message.json())));
+        self.queue(Message::text(message.json()), message.delivery());
+    }
+}
+
+impl<T> Client<T> where T: OutgoingMessage {
+    /// Queue a message for the websocket without waiting. What happens when the
+    /// queue is full depends on `delivery`.
+    pub fn queue(&self, message: Message, delivery: Delivery) {
+        let sender = match self.sender {
+            Some(ref sender) => sender,
+            None => return,
+        };
+
+        let room = match delivery {
+            Delivery::Droppable => sender.capacity() > sender.max_capacity() / 2,
+            Delivery::Required | Delivery::Closing => true,
+        };
+        if room {
+            match sender.try_send(message) {
+                Ok(()) => return,
+                Err(TrySendError::Full(_)) => (),
+                // The socket stopped taking messages, so this one will never be written
+                Err(TrySendError::Closed(_)) => {
+                    metrics::send_failed(&self.presentation);
+                    return;
+                }
+            }
         }
+
+        if delivery == Delivery::Required {
+            // Counted when the connection handles it, since a burst can end up here more than once
+            if let Some(ref closer) = self.closer {
+                let _ = closer.send(Disconnection::evicted());
+            }
+        } else {
+            metrics::message_dropped(&self.presentation);
+        }
+    }
+
+    /// Wait for room in the queue instead of dropping the message or
+    /// disconnecting. Only for tasks that can afford to wait, like replaying
+    /// a journal. Returns false once the connection has closed.
+    pub async fn queue_waiting(&self, message: Message) -> bool {
+        let Some(ref sender) = self.sender else {
+            return false;
+        };
+        if sender.send(message).await.is_err() {
+            metrics::send_failed(&self.presentation);
+            return false;
+        }
+        true
     }
 }
 
@@ -143,9 +244,13 @@ impl Users {
         self.guid_mapping.remove(&client.guid).is_some()
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = RefMulti<String, User>> {
+    pub fn iter(&self) -> impl Iterator<Item = RefMulti<'_, String, User>> {
         self.guid_mapping.iter()
     }
+
+    pub fn iter_mut(&self) -> impl Iterator<Item = RefMutMulti<'_, String, User>> {
+        self.guid_mapping.iter_mut()
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -165,6 +270,8 @@ pub struct JwtClaims {
     pub sub: String, // Contains the user's identifying information
     pub pid: String, // Presentation ID, should always match the kid in header to be valid
     pub exp: usize,
+    #[serde(default)]
+    pub iat: Option<u64>, // When the token was issued, it can't be from the future
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -173,3 +280,86 @@ pub struct ClientJoinPresentationData {
     pub claims: JwtClaims,
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUEUE_LENGTH: usize = 4;
+
+    /// A connected user and the other ends of its queue and closer
+    fn connected() -> (User, mpsc::Receiver<Message>, mpsc::UnboundedReceiver<Disconnection>) {
+        let (sender, messages) = mpsc::channel(QUEUE_LENGTH);
+        let (closer, disconnections) = mpsc::unbounded_channel();
+        let mut user = User::new("someone".to_string(), "presentation".to_string());
+        user.sender = Some(sender);
+        user.closer = Some(closer);
+        (user, messages, disconnections)
+    }
+
+    fn queue(user: &User, count: usize, delivery: Delivery) {
+        for _ in 0..count {
+            user.queue(Message::text("message"), delivery);
+        }
+    }
+
+    fn queued(messages: &mut mpsc::Receiver<Message>) -> usize {
+        std::iter::from_fn(|| messages.try_recv().ok()).count()
+    }
+
+    #[test]
+    fn droppable_messages_only_fill_half_the_queue() {
+        let (user, mut messages, mut disconnections) = connected();
+
+        queue(&user, QUEUE_LENGTH, Delivery::Droppable);
+        assert_eq!(queued(&mut messages), QUEUE_LENGTH / 2);
+        assert!(disconnections.try_recv().is_err());
+    }
+
+    #[test]
+    fn required_messages_use_the_rest_of_the_queue() {
+        let (user, mut messages, mut disconnections) = connected();
+
+        queue(&user, QUEUE_LENGTH, Delivery::Droppable);
+        queue(&user, QUEUE_LENGTH / 2, Delivery::Required);
+        assert!(disconnections.try_recv().is_err());
+        assert_eq!(queued(&mut messages), QUEUE_LENGTH);
+    }
+
+    #[test]
+    fn clients_that_can_not_take_required_messages_are_evicted() {
+        let (user, _messages, mut disconnections) = connected();
+
+        queue(&user, QUEUE_LENGTH + 1, Delivery::Required);
+        assert_eq!(disconnections.try_recv().unwrap(), Disconnection::evicted());
+    }
+
+    #[test]
+    fn closing_messages_are_dropped_when_the_queue_is_full() {
+        let (user, mut messages, mut disconnections) = connected();
+
+        queue(&user, QUEUE_LENGTH, Delivery::Required);
+        queue(&user, 1, Delivery::Closing);
+        assert!(disconnections.try_recv().is_err());
+        assert_eq!(queued(&mut messages), QUEUE_LENGTH);
+    }
+
+    #[test]
+    fn closed_connections_are_not_evicted() {
+        let (user, messages, mut disconnections) = connected();
+        drop(messages);
+
+        queue(&user, QUEUE_LENGTH + 1, Delivery::Required);
+        assert!(disconnections.try_recv().is_err());
+    }
+
+    #[test]
+    fn replaceable_messages_are_droppable() {
+        let questions = OutgoingUserMessage::Questions(Vec::new());
+        assert_eq!(questions.delivery(), Delivery::Droppable);
+        let queue = OutgoingPresenterMessage::QuestionQueue(Vec::new());
+        assert_eq!(queue.delivery(), Delivery::Droppable);
+        let closed = OutgoingUserMessage::PollClosed("poll".to_string());
+        assert_eq!(closed.delivery(), Delivery::Required);
+    }
+}
@@ -0,0 +1,145 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::{
+    health::Health,
+    journal::{JournalEntry, SharedJournal},
+    metrics,
+    report::Reports,
+    storage::{self, SharedStorage, Storage},
+    ws, Disconnection, Presentations,
+};
+
+/// Longest time between sweeps for idle presentations, in seconds
+const MAX_SWEEP_INTERVAL: u64 = 60;
+
+/// What clients are told when the server is going away. Their tokens stay
+/// valid so they can join again once the replacement is up.
+const SHUTDOWN_REASON: &str = "The server is restarting. Reconnect to continue the presentation.";
+
+/// End a presentation: disconnect everyone, keep its final report, forget it and
+/// remove its snapshot so it doesn't come back after a restart. Its journal is
+/// kept. Returns false if there was no presentation with that id.
+pub fn end_presentation(
+    presentation_id: &str,
+    presentations: &Presentations,
+    storage: Option<&dyn Storage>,
+    reports: &Reports,
+    reason: &str,
+) -> bool {
+    // Remove it first so nobody can join while everyone is being disconnected
+    let presentation = match presentations.remove(presentation_id) {
+        Some((_, presentation)) => presentation,
+        None => return false,
+    };
+
+    // Any snapshot being saved right now removes itself once it sees this
+    presentation.end();
+    presentation.disconnect_everyone(&Disconnection::new(reason, false));
+    reports.archive(&presentation);
+    metrics::forget_presentation(presentation_id);
+    presentation.record(&presentation.presenter_identity, JournalEntry::Ended);
+
+    if let Some(storage) = storage {
+        if let Err(e) = storage.remove(presentation_id) {
+            error!("Could not remove the snapshot of [{presentation_id}]: {e}");
+        }
+    }
+
+    info!("Ended presentation [{presentation_id}]: {reason}");
+    true
+}
+
+/// Periodically end presentations that nobody is connected to and that
+/// haven't been used for `idle_timeout` seconds
+pub fn spawn_idle_sweep(
+    presentations: Presentations,
+    storage: Option<SharedStorage>,
+    reports: Reports,
+    idle_timeout: u64,
+) {
+    tokio::task::spawn(async move {
+        let interval = idle_timeout.clamp(1, MAX_SWEEP_INTERVAL);
+        let mut timer = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            timer.tick().await;
+
+            // Collect the ids first so we don't hold map locks while removing
+            let idle: Vec<String> = presentations
+                .iter()
+                .filter(|x| !x.has_connections() && x.idle_for() >= idle_timeout.saturating_mul(1000))
+                .map(|x| x.key().clone())
+                .collect();
+
+            for presentation_id in idle {
+                end_presentation(
+                    &presentation_id,
+                    &presentations,
+                    storage.as_deref(),
+                    &reports,
+                    "Presentation ended after being idle",
+                );
+            }
+        }
+    });
+}
+
+/// Wait until the process is asked to stop
+#[cfg(unix)]
+pub async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    tokio::select! {
+        _ = terminate.recv() => info!("Received SIGTERM, shutting down"),
+        _ = tokio::signal::ctrl_c() => info!("Received SIGINT, shutting down"),
+    }
+}
+
+/// Wait until the process is asked to stop
+#[cfg(not(unix))]
+pub async fn shutdown_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+    info!("Received Ctrl-C, shutting down");
+}
+
+/// Get ready to exit: stop taking new clients, tell everyone connected to
+/// reconnect, save every presentation, wait for their sockets to close and
+/// write out the journal. Gives up waiting for sockets at `deadline`. Presentations aren't ended so the
+/// replacement instance can pick them up from storage or the journal.
+pub async fn drain(
+    presentations: &Presentations,
+    storage: Option<&SharedStorage>,
+    journal: Option<SharedJournal>,
+    health: &Health,
+    deadline: Instant,
+) {
+    health.shutting_down();
+
+    for presentation in presentations.iter() {
+        presentation.disconnect_everyone(&Disconnection::new(SHUTDOWN_REASON, true));
+    }
+
+    if let Some(storage) = storage {
+        storage::save_presentations(storage, presentations).await;
+    }
+
+    while ws::open_sockets() > 0 && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    match ws::open_sockets() {
+        0 => info!("Every client has been disconnected"),
+        open => warn!("Giving up on {open} websockets that didn't close in time"),
+    }
+
+    // Last, so it includes anything clients sent while they were leaving
+    if let Some(journal) = journal {
+        match tokio::task::spawn_blocking(move || journal.flush()).await {
+            Ok(Ok(())) => info!("The journal has been written out"),
+            Ok(Err(e)) => error!("Could not write out the journal: {e}"),
+            Err(e) => error!("Could not write out the journal: {e}"),
+        }
+    }
+}
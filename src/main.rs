@@ -1,11 +1,19 @@
 use dashmap::DashMap;
-use exhibit::authentication::join_presentation;
-use exhibit::{authentication::new_presentation, config, handler, Presentations};
+use exhibit::authentication::{self, join_presentation, report_access, KeySet, RegistrationKeys, SharedKeySet};
+use exhibit::health::Health;
+use exhibit::journal::{self, FileJournal, SharedJournal};
+use exhibit::report::Reports;
+use exhibit::storage::{self, FileStorage, SharedStorage};
+use exhibit::{authentication::new_presentation, config, handler, lifecycle, Presentations};
 
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::str::FromStr;
+use std::time::Duration;
 use std::{convert::Infallible, sync::Arc};
-use warp::Filter;
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+use warp::{Filter, Reply};
 
 #[tokio::main]
 async fn main() {
@@ -16,12 +24,83 @@ async fn main() {
     // Stores all the presenters and clients for all active presentations
     let presentations: Presentations = Arc::new(DashMap::new());
 
+    // If storage is configured, bring back everything from before the last
+    // restart and keep saving it from here on
+    let mut snapshots_taken_at = HashMap::new();
+    let storage: Option<SharedStorage> = configuration.storage.as_ref().map(|storage_config| {
+        let storage: SharedStorage = Arc::new(FileStorage::new(&storage_config.directory).unwrap());
+        snapshots_taken_at = storage::restore_presentations(storage.as_ref(), &presentations);
+        storage::spawn_snapshot_task(
+            storage.clone(),
+            presentations.clone(),
+            storage_config.snapshot_interval,
+        );
+        storage
+    });
+
+    // If journaling is configured, rebuild anything that was journaled but
+    // never snapshotted, catch snapshots up with what happened after them and
+    // record everything from here on
+    let journal: Option<SharedJournal> = match configuration.journal.as_ref() {
+        Some(journal_config) => {
+            let journal: SharedJournal =
+                Arc::new(FileJournal::new(&journal_config.directory).unwrap());
+            journal::restore_presentations(journal.clone(), &presentations, &snapshots_taken_at)
+                .await;
+            Some(journal)
+        }
+        None => None,
+    };
+
+    // Registration tokens can be checked against a key set that is reloaded
+    // as keys are rotated, as well as or instead of a single key
+    let key_set: Option<SharedKeySet> =
+        configuration.new_presentation_signing_keys.as_ref().map(|path| {
+            let key_set: SharedKeySet = Arc::new(
+                KeySet::load(path, configuration.new_presentation_signing_algorithms.clone())
+                    .unwrap(),
+            );
+            authentication::spawn_reloader(key_set.clone());
+            key_set
+        });
+    let registration_keys =
+        RegistrationKeys::new(configuration.new_presentation_signing_key.clone(), key_set);
+
+    // Reports of ended presentations, for presenters who ask after the talk
+    let reports = Reports::new(storage.clone());
+
+    if let Some(idle_timeout) = configuration.idle_timeout {
+        lifecycle::spawn_idle_sweep(
+            presentations.clone(),
+            storage.clone(),
+            reports.clone(),
+            idle_timeout,
+        );
+    }
+
     // APIs
-    let health_route = warp::path!("health").and_then(handler::health_handler);
+    let health = Arc::new(Health::new(storage.clone(), journal.clone()));
+    let live_route = warp::path!("health" / "live")
+        .or(warp::path!("health"))
+        .unify()
+        .and(with(health.clone()))
+        .and(with(presentations.clone()))
+        .and_then(handler::live_handler);
+    let ready_route = warp::path!("health" / "ready")
+        .and(with(health.clone()))
+        .and(with(presentations.clone()))
+        .and_then(handler::ready_handler);
+    let metrics_route = warp::path!("metrics")
+        .and(warp::get())
+        .and(with(presentations.clone()))
+        .and_then(handler::metrics_handler);
     let presentation_capture = presentations.clone();
     let client_ws_route = warp::path!("ws" / String / String)
         .and(warp::ws())
         .and(with(presentation_capture.clone()))
+        .and(with(health.clone()))
+        .and(with(configuration.heartbeat.clone()))
+        .and(with(configuration.client_queue_depth))
         .and_then(handler::ws_handler);
 
     let presentation_capture = presentations.clone();
@@ -31,25 +110,70 @@ async fn main() {
         .and(warp::body::content_length_limit(1024 * 4))
         .and(warp::body::form().and_then(move |provided_token| {
             new_presentation(
-                configuration.new_presentation_signing_key.clone(),
+                registration_keys.clone(),
                 provided_token,
                 presentation_capture.clone(),
             )
         }))
         .and(with(presentations.clone()))
+        .and(with(storage.clone()))
+        .and(with(journal.clone()))
+        .and(with(health.clone()))
         .and_then(handler::new_presentation_hander);
 
     let presentation_capture = presentations.clone();
+    let join_tokens = configuration.join_tokens.clone();
     let join_route = warp::path!("join")
         .and(warp::post())
         // Set maximum request size
         .and(warp::body::content_length_limit(1024 * 2))
         .and(warp::body::bytes().and_then(move |provided_token| {
-            join_presentation(provided_token, presentation_capture.clone())
+            join_presentation(provided_token, presentation_capture.clone(), join_tokens.clone())
         }))
         .and(with(presentations.clone()))
+        .and(with(health.clone()))
         .and_then(handler::join_handler);
 
+    let resume_route = warp::path!("resume" / String)
+        .and(warp::post())
+        // Set maximum request size
+        .and(warp::body::content_length_limit(1024))
+        .and(warp::body::json())
+        .and(with(presentations.clone()))
+        .and(with(health.clone()))
+        .and_then(handler::resume_handler);
+
+    let presentation_capture = presentations.clone();
+    let join_tokens = configuration.join_tokens.clone();
+    let end_route = warp::path!("end")
+        .and(warp::post())
+        // Set maximum request size
+        .and(warp::body::content_length_limit(1024 * 2))
+        .and(warp::body::bytes().and_then(move |provided_token| {
+            join_presentation(provided_token, presentation_capture.clone(), join_tokens.clone())
+        }))
+        .and(with(presentations.clone()))
+        .and(with(storage.clone()))
+        .and(with(reports.clone()))
+        .and_then(handler::end_handler);
+
+    let presentation_capture = presentations.clone();
+    let join_tokens = configuration.join_tokens.clone();
+    let report_route = warp::path!("report" / String)
+        .and(warp::post())
+        // Set maximum request size
+        .and(warp::body::content_length_limit(1024 * 2))
+        .and(warp::body::bytes().and_then(move |provided_token| {
+            report_access(
+                provided_token,
+                presentation_capture.clone(),
+                reports.clone(),
+                join_tokens.clone(),
+            )
+        }))
+        .and(with(presentations.clone()))
+        .and_then(handler::report_handler);
+
     // SPAs
     let join_spa = warp::path::end().and(warp::fs::file("webroot/join.html"));
     let presenter_spa = warp::path("present").and(warp::fs::file("webroot/present.html"));
@@ -59,9 +183,13 @@ async fn main() {
     let statics = warp::path("static").and(warp::fs::dir("webroot/"));
     let favicon = warp::path("favicon.ico").and(warp::fs::file("webroot/icons/favicon.ico"));
 
-    let all_routes = health_route
+    let all_routes = live_route
+        .or(ready_route)
         .or(new_presentation)
         .or(join_route)
+        .or(resume_route)
+        .or(end_route)
+        .or(report_route)
         .or(client_ws_route)
         .or(join_spa)
         .or(presenter_spa)
@@ -75,7 +203,45 @@ async fn main() {
     ))
     .unwrap();
 
-    warp::serve(all_routes).run(service_address).await
+    // Metrics get their own listener, share the public one only when asked
+    // to, or aren't served at all
+    let public_routes = match configuration.metrics_port {
+        Some(metrics_port) => {
+            let metrics_address = SocketAddr::from_str(&format!(
+                "{}:{metrics_port}",
+                configuration.service_address
+            ))
+            .unwrap();
+            tokio::task::spawn(warp::serve(metrics_route).run(metrics_address));
+            all_routes.map(Reply::into_response).boxed()
+        }
+        None if configuration.public_metrics => all_routes
+            .or(metrics_route)
+            .map(Reply::into_response)
+            .boxed(),
+        None => {
+            log::info!("Not serving /metrics, set metrics_port or public_metrics to");
+            all_routes.map(Reply::into_response).boxed()
+        }
+    };
+
+    let (stop, stopped) = oneshot::channel::<()>();
+    let (_, server) = warp::serve(public_routes)
+        .bind_with_graceful_shutdown(service_address, async {
+            let _ = stopped.await;
+        });
+    let server = tokio::task::spawn(server);
+
+    // Drain before the listener closes so clients can still be told where to go.
+    // Anything left at the deadline is dropped when the runtime shuts down.
+    lifecycle::shutdown_signal().await;
+    let deadline = Instant::now() + Duration::from_secs(configuration.shutdown_timeout);
+    lifecycle::drain(&presentations, storage.as_ref(), journal, &health, deadline).await;
+    let _ = stop.send(());
+    if tokio::time::timeout_at(deadline, server).await.is_err() {
+        log::warn!("Requests were still running at the shutdown deadline");
+    }
+    log::info!("Shut down");
 }
 
 fn with<T>(item: T) -> impl Filter<Extract = (T,), Error = Infallible> + Clone
@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
 
-use crate::{SlideSettings, VoteType};
+use crate::{
+    presentation::{QuizSettings, VotePolicy},
+    SlideSettings, VoteType,
+};
 
 pub mod presenter;
 pub mod user;
@@ -8,7 +11,23 @@ pub mod user;
 pub use presenter::*;
 pub use user::*;
 
-pub trait OutgoingMessage: Clone {}
+/// What happens to a message when the client it's for has fallen behind and
+/// its queue is full
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Delivery {
+    /// Dropped if the client is falling behind. A newer one will be along
+    /// soon. These only ever fill half the queue so there's room for the rest.
+    Droppable,
+    /// Has to be delivered. A client that can't take it is disconnected.
+    Required,
+    /// The last thing sent before the connection closes. Dropped if there is no room.
+    Closing,
+}
+
+pub trait OutgoingMessage: Clone {
+    /// How hard to try to get the message to a client that is falling behind
+    fn delivery(&self) -> Delivery;
+}
 
 #[derive(Debug, Deserialize)]
 pub enum IncomingMessage {
@@ -28,9 +47,47 @@ pub struct NewPollMessage {
     pub name: String,
     pub options: Vec<String>,
     pub vote_type: VoteType,
+    /// Whether users can change or withdraw their vote after casting it
+    #[serde(default)]
+    pub vote_policy: VotePolicy,
+    /// How many seconds the poll accepts votes for before the server closes it
+    #[serde(default)]
+    pub duration: Option<u64>,
+    /// Send the final results to users when the poll's time runs out
+    #[serde(default)]
+    pub share_results: bool,
+    /// Makes the poll a quiz question. Never send this to users, use
+    /// `without_answers` so the correct answer is not given away.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quiz: Option<QuizSettings>,
 }
 
-#[derive(Debug, Deserialize)]
+impl NewPollMessage {
+    /// A copy of the poll that is safe to send to users
+    pub fn without_answers(&self) -> Self {
+        Self {
+            quiz: None,
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct NewPromptMessage {
+    pub name: String,
+    pub question: String,
+    /// The longest response allowed in characters, or bytes in encrypted presentations
+    #[serde(default)]
+    pub max_length: Option<usize>,
+    /// Leave common words like "the" out of the word cloud
+    #[serde(default)]
+    pub remove_stop_words: bool,
+    /// Hold responses until a presenter approves them
+    #[serde(default)]
+    pub moderated: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct NewSlideMessage {
     pub slide: u64,
     pub slide_settings: SlideSettings,
@@ -3,17 +3,92 @@ use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 use warp::filters::ws::Message;
 
-use crate::{EmojiMessage, NewPollMessage, NewSlideMessage, OutgoingMessage};
+use crate::{heartbeat::ConnectionQuality, journal::JournalEvent, presentation::{ApprovedResponse, ChoiceTotals, EmojiBatch, EmojiMode, LeaderboardEntry, QuestionStatus, QueuedQuestion, RunoffResults, SlideActivity}, EmojiMessage, NewPollMessage, NewPromptMessage, NewSlideMessage, Delivery, OutgoingMessage};
 
 #[derive(Clone, Debug, Serialize)]
 pub enum OutgoingPresenterMessage {
     Emoji(EmojiMessage),
-    PollResults(HashMap<String, u64>),
+    /// Every emoji sent during a window, when emojis are being aggregated
+    EmojiBatch(EmojiBatch),
+    PollResults {
+        name: String,
+        totals: HashMap<String, ChoiceTotals>,
+        /// Instant-runoff rounds, only present for ranked polls
+        #[serde(skip_serializing_if = "Option::is_none")]
+        runoff: Option<RunoffResults>,
+    },
+    QuizRevealed {
+        name: String,
+        correct: Vec<String>,
+        voters: u64,
+        correct_voters: u64,
+    },
+    Leaderboard(Vec<LeaderboardEntry>),
+    /// A response to a moderated prompt that is waiting for approval
+    PendingResponse {
+        prompt_name: String,
+        id: u64,
+        identity: String,
+        text: String,
+    },
+    /// An approved response to a prompt in an encrypted presentation. These
+    /// have no word cloud so presenters get each response instead.
+    Response {
+        prompt_name: String,
+        id: u64,
+        identity: String,
+        text: String,
+    },
+    /// Every approved response to a prompt
+    Responses {
+        name: String,
+        responses: Vec<ApprovedResponse>,
+    },
+    WordCloud {
+        name: String,
+        words: HashMap<String, u64>,
+    },
+    /// The audience questions in the order they should be answered
+    QuestionQueue(Vec<QueuedQuestion>),
+    /// Live activity on a single slide, sent shortly after the audience interacts
+    SlideActivity(SlideActivity),
+    /// The activity on every slide so far
+    SlideBreakdown(Vec<SlideActivity>),
+    /// An event from the presentation's journal, sent while replaying it
+    ReplayEvent(JournalEvent),
+    /// Every event in the journal has been replayed
+    ReplayFinished,
+    /// Round trip times for the presenter and the audience. Sent with every
+    /// heartbeat and when asked for.
+    ConnectionQuality(ConnectionQuality),
     Error(String),
+    /// The connection is about to close
+    Disconnect {
+        reason: String,
+        /// True when the client should join again, like when the server is
+        /// restarting, and false when the presentation has ended
+        reconnect: bool,
+    },
     //NewSlide(SlideSettings),
 }
 
-impl OutgoingMessage for OutgoingPresenterMessage {}
+impl OutgoingMessage for OutgoingPresenterMessage {
+    fn delivery(&self) -> Delivery {
+        match self {
+            // Reactions and live stats are replaced by the next ones, and
+            // so is the question queue
+            Self::Emoji(_)
+            | Self::EmojiBatch(_)
+            | Self::SlideActivity(_)
+            | Self::ConnectionQuality(_)
+            | Self::QuestionQueue(_) => {
+                Delivery::Droppable
+            }
+            Self::Disconnect { .. } => Delivery::Closing,
+            _ => Delivery::Required,
+        }
+    }
+}
 
 impl OutgoingPresenterMessage {
     pub fn json(&self) -> String {
@@ -31,29 +106,190 @@ impl OutgoingPresenterMessage {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct GetPollTotalsMessage {
     pub name: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SubscribePollTotalsMessage {
+    pub name: String,
+    /// How many milliseconds of votes to collect into each update, between 50 and 10000
+    #[serde(default)]
+    pub window: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UnsubscribePollTotalsMessage {
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ClosePollMessage {
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ReopenPollMessage {
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DeletePollMessage {
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RevealQuizAnswerMessage {
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GetLeaderboardMessage {
+    /// Only send this many of the top entries
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SetPromptModerationMessage {
+    pub name: String,
+    pub moderated: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ModerateResponseMessage {
+    pub prompt_name: String,
+    pub id: u64,
+    pub approve: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GetWordCloudMessage {
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GetResponsesMessage {
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SetQuestionStatusMessage {
+    pub id: u64,
+    pub status: QuestionStatus,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GetSlideBreakdownMessage {}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GetConnectionQualityMessage {}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SetEmojiModeMessage {
+    pub mode: EmojiMode,
+    /// How many milliseconds of emojis to collect into each batch
+    #[serde(default)]
+    pub window: Option<u64>,
+    /// How many of the people who sent emojis to include with each batch
+    #[serde(default)]
+    pub sample: Option<usize>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct AddRatelimiterMessage {
     pub name: String,
     pub limiter: crate::ratelimiting::LimiterType,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct RemoveRatelimiterMessage {
     pub name: String,
 }
 
-#[derive(Debug, Deserialize)]
+fn default_replay_speed() -> f64 {
+    1.0
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ReplayMessage {
+    /// How many times faster than real time to replay the journal
+    #[serde(default = "default_replay_speed")]
+    pub speed: f64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum IncomingPresenterMessage {
     NewSlide(NewSlideMessage),
     NewPoll(NewPollMessage),
     GetPollTotals(GetPollTotalsMessage),
+    SubscribePollTotals(SubscribePollTotalsMessage),
+    UnsubscribePollTotals(UnsubscribePollTotalsMessage),
+    ClosePoll(ClosePollMessage),
+    ReopenPoll(ReopenPollMessage),
+    DeletePoll(DeletePollMessage),
+    RevealQuizAnswer(RevealQuizAnswerMessage),
+    GetLeaderboard(GetLeaderboardMessage),
+    NewPrompt(NewPromptMessage),
+    SetPromptModeration(SetPromptModerationMessage),
+    ModerateResponse(ModerateResponseMessage),
+    GetWordCloud(GetWordCloudMessage),
+    GetResponses(GetResponsesMessage),
+    SetQuestionStatus(SetQuestionStatusMessage),
+    GetSlideBreakdown(GetSlideBreakdownMessage),
+    GetConnectionQuality(GetConnectionQualityMessage),
+    SetEmojiMode(SetEmojiModeMessage),
     AddRatelimiter(AddRatelimiterMessage),
     RemoveRatelimiter(RemoveRatelimiterMessage),
+    Replay(ReplayMessage),
+}
+
+impl IncomingPresenterMessage {
+    /// The name of the message type, used to label metrics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::NewSlide(_) => "NewSlide",
+            Self::NewPoll(_) => "NewPoll",
+            Self::GetPollTotals(_) => "GetPollTotals",
+            Self::SubscribePollTotals(_) => "SubscribePollTotals",
+            Self::UnsubscribePollTotals(_) => "UnsubscribePollTotals",
+            Self::ClosePoll(_) => "ClosePoll",
+            Self::ReopenPoll(_) => "ReopenPoll",
+            Self::DeletePoll(_) => "DeletePoll",
+            Self::RevealQuizAnswer(_) => "RevealQuizAnswer",
+            Self::GetLeaderboard(_) => "GetLeaderboard",
+            Self::NewPrompt(_) => "NewPrompt",
+            Self::SetPromptModeration(_) => "SetPromptModeration",
+            Self::ModerateResponse(_) => "ModerateResponse",
+            Self::GetWordCloud(_) => "GetWordCloud",
+            Self::GetResponses(_) => "GetResponses",
+            Self::SetQuestionStatus(_) => "SetQuestionStatus",
+            Self::GetSlideBreakdown(_) => "GetSlideBreakdown",
+            Self::GetConnectionQuality(_) => "GetConnectionQuality",
+            Self::SetEmojiMode(_) => "SetEmojiMode",
+            Self::AddRatelimiter(_) => "AddRatelimiter",
+            Self::RemoveRatelimiter(_) => "RemoveRatelimiter",
+            Self::Replay(_) => "Replay",
+        }
+    }
+
+    /// Whether the message changes the presentation and so belongs in its
+    /// journal. Messages that only read state are left out.
+    pub fn is_journaled(&self) -> bool {
+        !matches!(
+            self,
+            Self::GetPollTotals(_)
+                | Self::SubscribePollTotals(_)
+                | Self::UnsubscribePollTotals(_)
+                | Self::GetLeaderboard(_)
+                | Self::GetWordCloud(_)
+                | Self::GetResponses(_)
+                | Self::GetSlideBreakdown(_)
+                | Self::GetConnectionQuality(_)
+                | Self::Replay(_)
+        )
+    }
 }
 
 impl std::fmt::Display for IncomingPresenterMessage {
@@ -68,8 +304,43 @@ impl std::fmt::Display for IncomingPresenterMessage {
                 write!(f, "New poll: {} with options {:?}", poll.name, poll.options)
             }
             Self::GetPollTotals(poll) => write!(f, "Get results for poll [{}]", poll.name),
+            Self::SubscribePollTotals(poll) => {
+                write!(f, "Subscribe to results for poll [{}]", poll.name)
+            }
+            Self::UnsubscribePollTotals(poll) => {
+                write!(f, "Unsubscribe from results for poll [{}]", poll.name)
+            }
+            Self::ClosePoll(poll) => write!(f, "Close poll [{}]", poll.name),
+            Self::ReopenPoll(poll) => write!(f, "Reopen poll [{}]", poll.name),
+            Self::DeletePoll(poll) => write!(f, "Delete poll [{}]", poll.name),
+            Self::RevealQuizAnswer(poll) => write!(f, "Reveal answer for quiz [{}]", poll.name),
+            Self::GetLeaderboard(_) => write!(f, "Get quiz leaderboard"),
+            Self::NewPrompt(prompt) => {
+                write!(f, "New prompt: {} asking {}", prompt.name, prompt.question)
+            }
+            Self::SetPromptModeration(prompt) => write!(
+                f,
+                "Set moderation for prompt [{}] to {}",
+                prompt.name, prompt.moderated
+            ),
+            Self::ModerateResponse(response) => write!(
+                f,
+                "{} response {} to prompt [{}]",
+                if response.approve { "Approve" } else { "Reject" },
+                response.id,
+                response.prompt_name
+            ),
+            Self::GetWordCloud(prompt) => write!(f, "Get word cloud for prompt [{}]", prompt.name),
+            Self::GetResponses(prompt) => write!(f, "Get responses to prompt [{}]", prompt.name),
+            Self::SetQuestionStatus(question) => {
+                write!(f, "Mark question {} as {:?}", question.id, question.status)
+            }
+            Self::GetSlideBreakdown(_) => write!(f, "Get activity for every slide"),
+            Self::GetConnectionQuality(_) => write!(f, "Get connection quality"),
+            Self::SetEmojiMode(msg) => write!(f, "Send emojis as {:?}", msg.mode),
             Self::AddRatelimiter(limiter) => write!(f, "Add ratelimiter: {:?}", limiter),
             Self::RemoveRatelimiter(limiter) => write!(f, "Remove ratelimiter: {:?}", limiter),
+            Self::Replay(replay) => write!(f, "Replay the journal at {}x speed", replay.speed),
         }
     }
 }
@@ -1,12 +1,53 @@
+use std::collections::HashMap;
+
 use serde::{Serialize, Deserialize};
 
-use crate::{ratelimiting::RatelimiterResponse, SlideSettings, EmojiMessage, OutgoingMessage, Vote, NewPollMessage};
+use crate::{presentation::{ChoiceTotals, QueuedQuestion, RunoffResults}, ratelimiting::RatelimiterResponse, SlideSettings, Delivery, EmojiMessage, OutgoingMessage, Vote, NewPollMessage, NewPromptMessage};
 
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum IncomingUserMessage {
     Emoji(EmojiMessage),
     Vote(Vote),
+    RetractVote(RetractVoteMessage),
+    TextResponse(TextResponseMessage),
+    Question(QuestionMessage),
+    UpvoteQuestion(UpvoteQuestionMessage),
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct QuestionMessage {
+    pub text: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UpvoteQuestionMessage {
+    pub id: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TextResponseMessage {
+    pub prompt_name: String,
+    pub text: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RetractVoteMessage {
+    pub poll_name: String,
+}
+
+impl IncomingUserMessage {
+    /// The name of the message type, used to label metrics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Emoji(_) => "Emoji",
+            Self::Vote(_) => "Vote",
+            Self::RetractVote(_) => "RetractVote",
+            Self::TextResponse(_) => "TextResponse",
+            Self::Question(_) => "Question",
+            Self::UpvoteQuestion(_) => "UpvoteQuestion",
+        }
+    }
 }
 
 impl std::fmt::Display for IncomingUserMessage {
@@ -22,22 +63,95 @@ impl std::fmt::Display for IncomingUserMessage {
                 "Vote for {:?}",
                 vote
             ),
+            Self::RetractVote(retract) => write!(
+                f,
+                "Retract vote in {}",
+                retract.poll_name
+            ),
+            Self::TextResponse(response) => write!(
+                f,
+                "Response to {}: {}",
+                response.prompt_name, response.text
+            ),
+            Self::Question(question) => write!(
+                f,
+                "Question: {}",
+                question.text
+            ),
+            Self::UpvoteQuestion(upvote) => write!(
+                f,
+                "Upvote question {}",
+                upvote.id
+            ),
         }
     }
 }
 
 #[derive(Clone, Debug, Serialize)]
 pub enum OutgoingUserMessage {
-    InitialPresentationData {title: String, settings: Option<SlideSettings>},
+    InitialPresentationData {
+        title: String,
+        settings: Option<SlideSettings>,
+        /// Send this to /resume to come back as the same user if the connection drops
+        resume_token: String,
+        /// The sequence number of the latest update
+        seq: u64,
+    },
     RatelimiterResponse(RatelimiterResponse),
     NewSlide(SlideSettings),
     NewPoll(NewPollMessage),
+    NewPrompt(NewPromptMessage),
+    /// The current audience questions, without who asked them. Each one
+    /// replaces the last so any of them can be dropped.
+    Questions(Vec<QueuedQuestion>),
+    /// The user's previous vote in the named poll was replaced
+    VoteReplaced(String),
+    /// The user's vote in the named poll was withdrawn
+    VoteWithdrawn(String),
+    /// The named poll is no longer accepting votes
+    PollClosed(String),
+    /// The named poll is accepting votes again
+    PollReopened(String),
+    /// The named poll has been removed from the presentation
+    PollDeleted(String),
+    /// The final results of a timed poll that shares its results
+    PollResults {
+        name: String,
+        totals: HashMap<String, ChoiceTotals>,
+        /// Instant-runoff rounds, only present for ranked polls
+        #[serde(skip_serializing_if = "Option::is_none")]
+        runoff: Option<RunoffResults>,
+    },
+    /// How the user did on a quiz question once the answer is revealed
+    QuizResult {
+        name: String,
+        answer: Vec<String>,
+        correct: bool,
+        points: u64,
+    },
     Success(String),
     Error(String),
-    Disconnect(String),
+    /// The connection is about to close
+    Disconnect {
+        reason: String,
+        /// True when the client should join again, like when the server is
+        /// restarting, and false when the presentation has ended
+        reconnect: bool,
+    },
 }
 
-impl OutgoingMessage for OutgoingUserMessage {}
+impl OutgoingMessage for OutgoingUserMessage {
+    fn delivery(&self) -> Delivery {
+        match self {
+            // Only tells the user whether their last message got through
+            Self::RatelimiterResponse(_) => Delivery::Droppable,
+            // The next change to the queue sends all of it again
+            Self::Questions(_) => Delivery::Droppable,
+            Self::Disconnect { .. } => Delivery::Closing,
+            _ => Delivery::Required,
+        }
+    }
+}
 
 impl OutgoingUserMessage {
     pub fn json(&self) -> String {
@@ -49,4 +163,26 @@ impl OutgoingUserMessage {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+/// A change to the presentation that every user is sent. `seq` goes up by one
+/// with each one so a client can tell when it has missed something. It sits
+/// next to the message so clients that don't care about it can ignore it.
+#[derive(Serialize)]
+pub struct SequencedUserMessage<'a> {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub message: &'a OutgoingUserMessage,
+}
+
+impl SequencedUserMessage<'_> {
+    pub fn json(&self) -> String {
+        match serde_json::to_string(&self) {
+            Ok(text) => text,
+            Err(e) => {
+                error!("Could not serialize sequenced user message: {e}");
+                String::new()
+            }
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::LazyLock,
+};
+
+use dashmap::DashMap;
+
+use crate::Presentations;
+
+/// Every counter the server keeps. Counters are process wide so they can be
+/// bumped from anywhere without threading a handle through every call.
+static COUNTERS: LazyLock<DashMap<(&'static str, String), u64>> = LazyLock::new(DashMap::new);
+
+const MESSAGES_RECEIVED: &str = "exhibit_messages_received_total";
+const PRESENTATION_MESSAGES_RECEIVED: &str = "exhibit_presentation_messages_received_total";
+const RATELIMITER_CHECKS: &str = "exhibit_ratelimiter_checks_total";
+const PRESENTATION_RATELIMITER_CHECKS: &str = "exhibit_presentation_ratelimiter_checks_total";
+const JOIN_REJECTIONS: &str = "exhibit_join_rejections_total";
+const PRESENTATION_JOIN_REJECTIONS: &str = "exhibit_presentation_join_rejections_total";
+const SEND_FAILURES: &str = "exhibit_websocket_send_failures_total";
+const PRESENTATION_SEND_FAILURES: &str = "exhibit_presentation_websocket_send_failures_total";
+const MESSAGES_DROPPED: &str = "exhibit_messages_dropped_total";
+const PRESENTATION_MESSAGES_DROPPED: &str = "exhibit_presentation_messages_dropped_total";
+const SLOW_CLIENTS: &str = "exhibit_slow_clients_disconnected_total";
+const PRESENTATION_SLOW_CLIENTS: &str = "exhibit_presentation_slow_clients_disconnected_total";
+
+const HELP: &[(&str, &str)] = &[
+    (MESSAGES_RECEIVED, "Messages received from clients by type"),
+    (PRESENTATION_MESSAGES_RECEIVED, "Messages received from clients by presentation and type"),
+    (RATELIMITER_CHECKS, "Ratelimiter decisions by limiter and outcome"),
+    (PRESENTATION_RATELIMITER_CHECKS, "Ratelimiter decisions by presentation, limiter and outcome"),
+    (JOIN_REJECTIONS, "Requests to join a presentation that were rejected, by reason"),
+    (PRESENTATION_JOIN_REJECTIONS, "Rejected requests to join a presentation that exists, by presentation and reason"),
+    (SEND_FAILURES, "Messages that could not be written to a websocket"),
+    (PRESENTATION_SEND_FAILURES, "Messages that could not be written to a websocket, by presentation"),
+    (MESSAGES_DROPPED, "Messages dropped because a client's queue was full"),
+    (PRESENTATION_MESSAGES_DROPPED, "Messages dropped because a client's queue was full, by presentation"),
+    (SLOW_CLIENTS, "Clients disconnected because their queue was full"),
+    (PRESENTATION_SLOW_CLIENTS, "Clients disconnected because their queue was full, by presentation"),
+];
+
+/// Why a request to join a presentation was turned away
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JoinRejection {
+    /// The token could not be parsed
+    BadJwt,
+    /// The token's signature doesn't match the presentation's key
+    BadSignature,
+    /// The token's `exp` has passed
+    Expired,
+    /// The token's `nbf` or `iat` is still in the future
+    NotYetValid,
+    /// The token wasn't issued by a trusted issuer
+    WrongIssuer,
+    /// The token isn't meant for this server
+    WrongAudience,
+    /// The token's `pid` claim names a different presentation than its `kid`
+    PresentationMismatch,
+    /// The token does not say which presentation it is for
+    UnknownKid,
+    /// The token is for a presentation that doesn't exist
+    UnknownPresentation,
+    /// The token is signed with an algorithm the presentation doesn't allow
+    DisallowedAlgorithm,
+    /// A resume token that was never issued, was already used, has expired or
+    /// whose connection is still open
+    BadResumeToken,
+}
+
+impl JoinRejection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::BadJwt => "bad_jwt",
+            Self::BadSignature => "bad_signature",
+            Self::Expired => "expired",
+            Self::NotYetValid => "not_yet_valid",
+            Self::WrongIssuer => "wrong_issuer",
+            Self::WrongAudience => "wrong_audience",
+            Self::PresentationMismatch => "presentation_mismatch",
+            Self::UnknownKid => "unknown_kid",
+            Self::UnknownPresentation => "unknown_presentation",
+            Self::DisallowedAlgorithm => "disallowed_algorithm",
+            Self::BadResumeToken => "bad_resume_token",
+        }
+    }
+}
+
+/// Escape a label value as the exposition format requires
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn increment(name: &'static str, labels: String) {
+    *COUNTERS.entry((name, labels)).or_insert(0) += 1;
+}
+
+/// Labels for a per presentation series. The presentation always comes first
+/// so its series can be found again when it ends.
+fn presentation_labels(presentation_id: &str, rest: &str) -> String {
+    if rest.is_empty() {
+        format!("presentation=\"{}\"", label(presentation_id))
+    } else {
+        format!("presentation=\"{}\",{rest}", label(presentation_id))
+    }
+}
+
+pub fn message_received(presentation_id: &str, kind: &str) {
+    let labels = format!("type=\"{}\"", label(kind));
+    increment(PRESENTATION_MESSAGES_RECEIVED, presentation_labels(presentation_id, &labels));
+    increment(MESSAGES_RECEIVED, labels);
+}
+
+pub fn ratelimiter_checked(presentation_id: &str, limiter: &str, allowed: bool) {
+    let outcome = if allowed { "allowed" } else { "blocked" };
+    let labels = format!("limiter=\"{}\",outcome=\"{outcome}\"", label(limiter));
+    increment(PRESENTATION_RATELIMITER_CHECKS, presentation_labels(presentation_id, &labels));
+    increment(RATELIMITER_CHECKS, labels);
+}
+
+/// Count a rejected join. The presentation is only given when it exists, so
+/// made up ids can't create new series.
+pub fn join_rejected(reason: JoinRejection, presentation_id: Option<&str>) {
+    let labels = format!("reason=\"{}\"", reason.as_str());
+    if let Some(presentation_id) = presentation_id {
+        increment(PRESENTATION_JOIN_REJECTIONS, presentation_labels(presentation_id, &labels));
+    }
+    increment(JOIN_REJECTIONS, labels);
+}
+
+pub fn send_failed(presentation_id: &str) {
+    increment(PRESENTATION_SEND_FAILURES, presentation_labels(presentation_id, ""));
+    increment(SEND_FAILURES, String::new());
+}
+
+pub fn message_dropped(presentation_id: &str) {
+    increment(PRESENTATION_MESSAGES_DROPPED, presentation_labels(presentation_id, ""));
+    increment(MESSAGES_DROPPED, String::new());
+}
+
+pub fn client_evicted(presentation_id: &str) {
+    increment(PRESENTATION_SLOW_CLIENTS, presentation_labels(presentation_id, ""));
+    increment(SLOW_CLIENTS, String::new());
+}
+
+/// Drop every series for a presentation that has ended. The global
+/// series keep their totals.
+pub fn forget_presentation(presentation_id: &str) {
+    let prefix = presentation_labels(presentation_id, "");
+    COUNTERS.retain(|(_, labels), _| {
+        !(labels == &prefix || labels.starts_with(&format!("{prefix},")))
+    });
+}
+
+fn write_series(out: &mut String, name: &str, labels: &str, value: impl std::fmt::Display) {
+    if labels.is_empty() {
+        let _ = writeln!(out, "{name} {value}");
+    } else {
+        let _ = writeln!(out, "{name}{{{labels}}} {value}");
+    }
+}
+
+fn write_gauge(out: &mut String, name: &str, help: &str, series: &[(String, usize)]) {
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} gauge");
+    for (labels, value) in series {
+        write_series(out, name, labels, value);
+    }
+}
+
+/// Everything in the Prometheus text exposition format
+pub fn render(presentations: &Presentations) -> String {
+    let mut out = String::new();
+
+    // Gauges are read from the presentations as they are right now
+    let mut users = vec![];
+    let mut presenters = vec![];
+    for presentation in presentations.iter() {
+        let labels = presentation_labels(&presentation.id, "");
+        users.push((labels.clone(), presentation.connected_users()));
+        presenters.push((labels, presentation.connected_presenters()));
+    }
+
+    write_gauge(
+        &mut out,
+        "exhibit_presentations_active",
+        "Presentations currently running",
+        &[(String::new(), presentations.len())],
+    );
+    write_gauge(
+        &mut out,
+        "exhibit_users_connected",
+        "Users with an open websocket",
+        &[(String::new(), users.iter().map(|x| x.1).sum())],
+    );
+    write_gauge(
+        &mut out,
+        "exhibit_presenters_connected",
+        "Presenters with an open websocket",
+        &[(String::new(), presenters.iter().map(|x| x.1).sum())],
+    );
+    write_gauge(
+        &mut out,
+        "exhibit_presentation_users_connected",
+        "Users with an open websocket by presentation",
+        &users,
+    );
+    write_gauge(
+        &mut out,
+        "exhibit_presentation_presenters_connected",
+        "Presenters with an open websocket by presentation",
+        &presenters,
+    );
+
+    let mut counters: BTreeMap<&str, Vec<(String, u64)>> = BTreeMap::new();
+    for x in COUNTERS.iter() {
+        let (name, labels) = x.key();
+        counters.entry(name).or_default().push((labels.clone(), *x.value()));
+    }
+
+    for (name, help) in HELP {
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter");
+        let mut series = counters.remove(name).unwrap_or_default();
+        series.sort();
+        for (labels, value) in series {
+            write_series(&mut out, name, &labels, value);
+        }
+    }
+
+    out
+}
@@ -0,0 +1,166 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{processor::broadcast_to_presenters, EmojiMessage, OutgoingPresenterMessage, Presenters};
+
+/// The shortest and longest time emojis can be collected for, in milliseconds
+const MIN_WINDOW_MS: u64 = 50;
+const MAX_WINDOW_MS: u64 = 10_000;
+/// The most identities that can be sent along with a batch
+const MAX_SAMPLE: usize = 50;
+
+/// How emojis from the audience reach the presenter
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum EmojiMode {
+    /// One message per emoji as soon as it arrives
+    #[default]
+    Raw,
+    /// Emojis are collected for a window and sent as one message with counts
+    Aggregated,
+}
+
+fn default_window() -> u64 {
+    500
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EmojiBatchSettings {
+    pub mode: EmojiMode,
+    /// How many milliseconds of emojis go into each batch
+    #[serde(default = "default_window")]
+    pub window: u64,
+    /// How many of the people who sent emojis to include with each batch
+    #[serde(default)]
+    pub sample: usize,
+}
+
+impl Default for EmojiBatchSettings {
+    fn default() -> Self {
+        Self {
+            mode: EmojiMode::Raw,
+            window: default_window(),
+            sample: 0,
+        }
+    }
+}
+
+/// How many of one emoji at one size were sent during a batch
+#[derive(Clone, Debug, Serialize)]
+pub struct EmojiCount {
+    pub emoji: String,
+    pub size: u8,
+    pub count: u64,
+}
+
+/// Every emoji sent during one window
+#[derive(Clone, Debug, Serialize)]
+pub struct EmojiBatch {
+    /// How long the batch covers, in milliseconds
+    pub window: u64,
+    pub counts: Vec<EmojiCount>,
+    /// The first few people to send an emoji in the window, if the presenter asked for them
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sample: Vec<String>,
+}
+
+#[derive(Default)]
+struct Batch {
+    counts: BTreeMap<(String, u8), u64>,
+    sample: Vec<String>,
+}
+
+/// Collects emojis for presenters who would rather get one message per
+/// window than one per emoji. In raw mode it does nothing.
+#[derive(Clone, Default)]
+pub struct EmojiBatcher {
+    settings: Arc<Mutex<EmojiBatchSettings>>,
+    batch: Arc<Mutex<Batch>>,
+    /// Whether a batch is waiting to be sent
+    pending: Arc<AtomicBool>,
+}
+
+impl EmojiBatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn restore(settings: EmojiBatchSettings) -> Self {
+        let batcher = Self::new();
+        batcher.configure(settings);
+        batcher
+    }
+
+    pub fn settings(&self) -> EmojiBatchSettings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    /// Switch modes. Anything already collected is still sent when its window ends.
+    pub fn configure(&self, mut settings: EmojiBatchSettings) {
+        settings.window = settings.window.clamp(MIN_WINDOW_MS, MAX_WINDOW_MS);
+        settings.sample = settings.sample.min(MAX_SAMPLE);
+        *self.settings.lock().unwrap() = settings;
+    }
+
+    pub fn is_aggregating(&self) -> bool {
+        self.settings.lock().unwrap().mode == EmojiMode::Aggregated
+    }
+
+    /// Add an emoji to the current batch, starting a new window if there isn't one
+    pub fn add(&self, emoji: &EmojiMessage, identity: &str, presenters: Presenters) {
+        let settings = self.settings();
+        {
+            let mut batch = self.batch.lock().unwrap();
+            *batch
+                .counts
+                .entry((emoji.emoji.clone(), emoji.size))
+                .or_insert(0) += 1;
+            if batch.sample.len() < settings.sample && !batch.sample.iter().any(|x| x == identity) {
+                batch.sample.push(identity.to_string());
+            }
+        }
+
+        // A batch is already waiting to go out and will include this emoji
+        if self.pending.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let batcher = self.clone();
+        tokio::task::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(settings.window)).await;
+            // Clear pending while holding the batch so an emoji that lands
+            // right now starts the next window instead of being lost
+            let batch = {
+                let mut batch = batcher.batch.lock().unwrap();
+                batcher.pending.store(false, Ordering::SeqCst);
+                std::mem::take(&mut *batch)
+            };
+            // An earlier window can pick up emojis added just as it was scheduled
+            if batch.counts.is_empty() {
+                return;
+            }
+
+            let counts = batch
+                .counts
+                .into_iter()
+                .map(|((emoji, size), count)| EmojiCount { emoji, size, count })
+                .collect();
+            broadcast_to_presenters(
+                OutgoingPresenterMessage::EmojiBatch(EmojiBatch {
+                    window: settings.window,
+                    counts,
+                    sample: batch.sample,
+                }),
+                presenters,
+            )
+            .await;
+        });
+    }
+}
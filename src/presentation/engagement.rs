@@ -0,0 +1,285 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use dashmap::{DashMap, DashSet};
+use serde::{Deserialize, Serialize};
+
+use super::now_millis;
+use crate::{OutgoingPresenterMessage, Presenters};
+
+/// How long to collect interactions on a slide before presenters are sent
+/// its updated activity
+const SLIDE_ACTIVITY_WINDOW_MS: u64 = 1000;
+
+/// Everything the audience did while a slide was showing
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SlideActivity {
+    pub slide: u64,
+    pub emojis: BTreeMap<String, u64>,
+    pub votes: u64,
+    pub questions: u64,
+}
+
+impl SlideActivity {
+    pub fn total_emojis(&self) -> u64 {
+        self.emojis.values().sum()
+    }
+}
+
+/// How many people were connected and for how long
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct Audience {
+    current: u64,
+    peak: u64,
+    /// Sum of audience size multiplied by milliseconds at that size
+    weighted: u128,
+    /// When the audience size last changed
+    changed_at: u64,
+}
+
+/// Everything needed to bring back engagement stats after a restart
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct EngagementSnapshot {
+    started_at: u64,
+    current_slide: u64,
+    audience: Audience,
+    /// Slide number to emoji to count. Tuple keys can't be JSON object keys
+    /// so this is stored flattened.
+    emojis: Vec<(u64, String, u64)>,
+    reactions: Vec<(u64, u64)>,
+    votes: Vec<(u64, u64)>,
+    questions: Vec<(u64, u64)>,
+}
+
+/// Running stats about how the audience took part in a presentation
+#[derive(Clone)]
+pub struct Engagement {
+    started_at: u64,
+    current_slide: Arc<AtomicU64>,
+    audience: Arc<Mutex<Audience>>,
+    /// How many times each emoji was sent on each slide
+    emojis: Arc<DashMap<(u64, String), u64>>,
+    /// How many emojis were sent each second, keyed by seconds since the epoch
+    reactions: Arc<DashMap<u64, u64>>,
+    /// How many votes were cast on each slide
+    votes: Arc<DashMap<u64, u64>>,
+    /// How many questions were asked on each slide
+    questions: Arc<DashMap<u64, u64>>,
+    /// Slides with interactions that presenters haven't been sent yet
+    pending: Arc<DashSet<u64>>,
+}
+
+impl Default for Engagement {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engagement {
+    pub fn new() -> Self {
+        let started_at = now_millis();
+        Self {
+            started_at,
+            current_slide: Arc::new(AtomicU64::new(0)),
+            audience: Arc::new(Mutex::new(Audience {
+                changed_at: started_at,
+                ..Default::default()
+            })),
+            emojis: Arc::new(DashMap::new()),
+            reactions: Arc::new(DashMap::new()),
+            votes: Arc::new(DashMap::new()),
+            questions: Arc::new(DashMap::new()),
+            pending: Arc::new(DashSet::new()),
+        }
+    }
+
+    pub fn snapshot(&self) -> EngagementSnapshot {
+        EngagementSnapshot {
+            started_at: self.started_at,
+            current_slide: self.current_slide(),
+            audience: self.audience.lock().unwrap().clone(),
+            emojis: self
+                .emojis
+                .iter()
+                .map(|x| (x.key().0, x.key().1.clone(), *x.value()))
+                .collect(),
+            reactions: self.reactions.iter().map(|x| (*x.key(), *x.value())).collect(),
+            votes: self.votes.iter().map(|x| (*x.key(), *x.value())).collect(),
+            questions: self.questions.iter().map(|x| (*x.key(), *x.value())).collect(),
+        }
+    }
+
+    pub fn restore(snapshot: EngagementSnapshot) -> Self {
+        // Nobody is connected after a restart and the time we were down
+        // shouldn't count towards the average
+        let mut audience = snapshot.audience;
+        audience.current = 0;
+        audience.changed_at = now_millis();
+
+        Self {
+            started_at: snapshot.started_at,
+            current_slide: Arc::new(AtomicU64::new(snapshot.current_slide)),
+            audience: Arc::new(Mutex::new(audience)),
+            emojis: Arc::new(
+                snapshot
+                    .emojis
+                    .into_iter()
+                    .map(|(slide, emoji, count)| ((slide, emoji), count))
+                    .collect(),
+            ),
+            reactions: Arc::new(snapshot.reactions.into_iter().collect()),
+            votes: Arc::new(snapshot.votes.into_iter().collect()),
+            questions: Arc::new(snapshot.questions.into_iter().collect()),
+            pending: Arc::new(DashSet::new()),
+        }
+    }
+
+    /// When the presentation was created, in milliseconds since the epoch
+    pub fn started_at(&self) -> u64 {
+        self.started_at
+    }
+
+    pub fn current_slide(&self) -> u64 {
+        self.current_slide.load(Ordering::Relaxed)
+    }
+
+    pub fn set_slide(&self, slide: u64) {
+        self.current_slide.store(slide, Ordering::Relaxed);
+    }
+
+    pub fn audience_changed(&self, size: u64) {
+        let now = now_millis();
+        let mut audience = self.audience.lock().unwrap();
+        audience.weighted += audience.current as u128 * now.saturating_sub(audience.changed_at) as u128;
+        audience.changed_at = now;
+        audience.current = size;
+        audience.peak = audience.peak.max(size);
+    }
+
+    pub fn peak_audience(&self) -> u64 {
+        self.audience.lock().unwrap().peak
+    }
+
+    /// The audience size averaged over the whole presentation
+    pub fn average_audience(&self) -> f64 {
+        let now = now_millis();
+        let audience = self.audience.lock().unwrap();
+        let weighted = audience.weighted
+            + audience.current as u128 * now.saturating_sub(audience.changed_at) as u128;
+        let elapsed = now.saturating_sub(self.started_at);
+        if elapsed == 0 {
+            return audience.current as f64;
+        }
+        weighted as f64 / elapsed as f64
+    }
+
+    /// Count an emoji against the current slide
+    pub fn emoji_sent(&self, emoji: &str, presenters: Presenters) {
+        let slide = self.current_slide();
+        *self.emojis.entry((slide, emoji.to_string())).or_insert(0) += 1;
+        *self.reactions.entry(now_millis() / 1000).or_insert(0) += 1;
+        self.slide_changed(slide, presenters);
+    }
+
+    /// Count a vote against the current slide
+    pub fn vote_cast(&self, presenters: Presenters) {
+        let slide = self.current_slide();
+        *self.votes.entry(slide).or_insert(0) += 1;
+        self.slide_changed(slide, presenters);
+    }
+
+    /// Count a question against the current slide
+    pub fn question_asked(&self, presenters: Presenters) {
+        let slide = self.current_slide();
+        *self.questions.entry(slide).or_insert(0) += 1;
+        self.slide_changed(slide, presenters);
+    }
+
+    /// Send presenters the activity on a slide once the window has passed.
+    /// Any further interactions inside that window are included in the same update.
+    fn slide_changed(&self, slide: u64, presenters: Presenters) {
+        if !self.pending.insert(slide) {
+            return;
+        }
+
+        let engagement = self.clone();
+        tokio::task::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(SLIDE_ACTIVITY_WINDOW_MS)).await;
+            engagement.pending.remove(&slide);
+
+            let message = OutgoingPresenterMessage::SlideActivity(engagement.slide_activity(slide));
+            for presenter in presenters.iter() {
+                presenter.send_ignore_fail(message.clone());
+            }
+        });
+    }
+
+    pub fn slide_activity(&self, slide: u64) -> SlideActivity {
+        SlideActivity {
+            slide,
+            emojis: self
+                .emojis
+                .iter()
+                .filter(|x| x.key().0 == slide)
+                .map(|x| (x.key().1.clone(), *x.value()))
+                .collect(),
+            votes: self.votes.get(&slide).map(|x| *x).unwrap_or(0),
+            questions: self.questions.get(&slide).map(|x| *x).unwrap_or(0),
+        }
+    }
+
+    /// The activity on every slide that had any, in slide order
+    pub fn slides(&self) -> Vec<SlideActivity> {
+        let mut slides: BTreeMap<u64, SlideActivity> = BTreeMap::new();
+        fn activity(slides: &mut BTreeMap<u64, SlideActivity>, slide: u64) -> &mut SlideActivity {
+            slides.entry(slide).or_insert_with(|| SlideActivity {
+                slide,
+                ..Default::default()
+            })
+        }
+
+        for x in self.emojis.iter() {
+            let (slide, emoji) = x.key();
+            activity(&mut slides, *slide)
+                .emojis
+                .insert(emoji.clone(), *x.value());
+        }
+        for x in self.votes.iter() {
+            activity(&mut slides, *x.key()).votes = *x.value();
+        }
+        for x in self.questions.iter() {
+            activity(&mut slides, *x.key()).questions = *x.value();
+        }
+        slides.into_values().collect()
+    }
+
+    /// Emoji counts across every slide
+    pub fn emoji_totals(&self) -> BTreeMap<String, u64> {
+        let mut totals = BTreeMap::new();
+        for x in self.emojis.iter() {
+            *totals.entry(x.key().1.clone()).or_insert(0) += *x.value();
+        }
+        totals
+    }
+
+    /// Emojis sent in each `bucket` seconds since the presentation started.
+    /// Buckets without any emojis are included so the series has no gaps.
+    pub fn reactions(&self, bucket: u64) -> Vec<u64> {
+        let start = self.started_at / 1000;
+        let buckets = (now_millis() / 1000).saturating_sub(start) / bucket + 1;
+        let mut series = vec![0; buckets as usize];
+        for x in self.reactions.iter() {
+            let index = (x.key().saturating_sub(start) / bucket) as usize;
+            if let Some(count) = series.get_mut(index) {
+                *count += *x.value();
+            }
+        }
+        series
+    }
+}
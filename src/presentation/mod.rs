@@ -1,15 +1,41 @@
+mod emoji_batch;
+mod engagement;
 mod poll;
+mod prompt;
+mod questions;
+mod quiz;
+mod resume;
+mod runoff;
+mod snapshot;
+mod subscriptions;
+mod timer;
+mod updates;
 
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc,
+};
 
 use dashmap::DashMap;
-use jsonwebtoken::DecodingKey;
-use tokio::sync::RwLock;
+use jsonwebtoken::Algorithm;
+use tokio::sync::{mpsc, RwLock};
 
+pub use self::emoji_batch::{EmojiBatch, EmojiBatchSettings, EmojiBatcher, EmojiCount, EmojiMode};
+pub use self::engagement::{Engagement, EngagementSnapshot, SlideActivity};
 pub use self::poll::*;
+pub use self::prompt::{ApprovedResponse, Prompt, Prompts, ResponseState, TextResponse};
+pub use self::questions::{QueuedQuestion, QuestionStatus, Questions};
+pub use self::quiz::{LeaderboardEntry, QuizAnswer, QuizReveal, QuizSettings};
+pub use self::resume::ResumeTokens;
+pub use self::runoff::{RunoffResults, RunoffRound};
+pub use self::snapshot::PresentationSnapshot;
+pub use self::subscriptions::PollSubscriptions;
+pub use self::updates::Updates;
 use crate::{
-    ratelimiting::{time::TimeLimiter, Ratelimiter},
-    Presenters, SlideSettings, Users,
+    authentication::VerificationKey,
+    journal::SharedJournal,
+    ratelimiting::{time::TimeLimiter, LimiterType, Ratelimiter},
+    Disconnection, Presenters, SlideSettings, Users,
 };
 
 #[derive(Clone)]
@@ -20,6 +46,20 @@ pub struct PresentationData {
     /// Created poll in the presentation. The key is the name of the poll.
     /// The value is map from user identity to what their answer was.
     pub polls: Polls,
+    /// Presenters who want poll totals pushed to them as votes come in
+    pub poll_subscriptions: PollSubscriptions,
+    /// Free text questions the presenter has asked. The key is the name of the prompt.
+    pub prompts: Prompts,
+    /// Questions the audience has asked the presenter
+    pub questions: Questions,
+    /// Audience size and reactions, used for the report after the talk
+    pub engagement: Engagement,
+    /// Whether emojis reach presenters one at a time or in batches
+    pub emoji_batcher: EmojiBatcher,
+    /// Numbered slide, poll and prompt changes sent to users
+    pub updates: Updates,
+    /// Lets users with dropped connections come back without their JWT
+    pub resume_tokens: ResumeTokens,
 }
 
 impl PresentationData {
@@ -27,6 +67,13 @@ impl PresentationData {
         Self {
             title,
             polls: Polls::new(),
+            poll_subscriptions: PollSubscriptions::new(),
+            prompts: Prompts::new(),
+            questions: Questions::new(),
+            engagement: Engagement::new(),
+            emoji_batcher: EmojiBatcher::new(),
+            updates: Updates::new(),
+            resume_tokens: ResumeTokens::new(),
         }
     }
 }
@@ -37,11 +84,26 @@ pub struct Presentation {
     pub presenter_identity: String,
     pub users: Users,
     pub presenters: Presenters,
-    pub authentication_key: DecodingKey,
+    pub authentication_key: VerificationKey,
+    /// The PEM or JWK the authentication key was parsed from. Keys can't be
+    /// serialized so this is what gets saved in snapshots.
+    pub authentication_key_source: String,
+    /// The algorithms join tokens were allowed to use when the presentation
+    /// was created. Empty means the usual one for the key.
+    pub authentication_algorithms: Vec<Algorithm>,
     pub ratelimiter: Arc<Ratelimiter>,
     pub slide_settings: Arc<RwLock<Option<SlideSettings>>>,
     pub encrypted: bool,
+    /// Where accepted events are recorded. Presentations that are being
+    /// rebuilt from a journal don't have one so they don't record twice.
+    pub journal: Option<SharedJournal>,
     presentation_data: PresentationData,
+    /// When someone last joined or sent a message, in milliseconds since the epoch
+    last_activity: Arc<AtomicU64>,
+    /// Set once the presentation has been ended so nothing saves it again
+    ended: Arc<AtomicBool>,
+    /// Tells the presentation's poll timer about polls with a time limit
+    poll_timer: mpsc::UnboundedSender<String>,
 }
 
 impl Presentation {
@@ -49,37 +111,178 @@ impl Presentation {
         presentation_id: String,
         presenter_identity: String,
         encrypted: bool,
-        authentication_key: DecodingKey,
+        authentication_key_source: String,
+        authentication_algorithms: Vec<Algorithm>,
         title: String,
-    ) -> Self {
+    ) -> Result<Self, String> {
+        let presentation = Self::from_parts(
+            presentation_id,
+            presenter_identity,
+            encrypted,
+            authentication_key_source,
+            authentication_algorithms,
+            PresentationData::new(title),
+            None,
+        )?;
+
         // Create a default 5s ratelimiter
-        let ratelimiter = Arc::new(Ratelimiter::new());
-        ratelimiter.add_ratelimit("5s".to_string(), Arc::new(TimeLimiter::new(5)));
+        presentation.ratelimiter.add_configured_ratelimit(
+            "5s".to_string(),
+            LimiterType::Time(TimeLimiter::new(5)),
+        );
 
         /*
-        ratelimiter.add_ratelimit(
+        presentation.ratelimiter.add_ratelimit(
             "value".to_string(),
-            Arc::new(ValueLimiter::new(3, 3, 3, 6, 10)),
+            Arc::new(ValueLimiter::new(3, 3, 3, 6, 10, 0)),
         ); */
 
-        Self {
+        Ok(presentation)
+    }
+
+    /// Build a presentation around existing data. Used both for brand new
+    /// presentations and ones being restored from a snapshot. It starts
+    /// without any ratelimits.
+    fn from_parts(
+        presentation_id: String,
+        presenter_identity: String,
+        encrypted: bool,
+        authentication_key_source: String,
+        authentication_algorithms: Vec<Algorithm>,
+        presentation_data: PresentationData,
+        slide_settings: Option<SlideSettings>,
+    ) -> Result<Self, String> {
+        let authentication_key = VerificationKey::parse(&authentication_key_source)
+            .and_then(|key| key.with_algorithms(&authentication_algorithms))
+            .map_err(|e| format!("Invalid authentication key: {e}"))?;
+
+        let users = Users::new();
+        let presenters: Presenters = Arc::new(DashMap::new());
+        let poll_timer = timer::spawn_poll_timer(
+            presentation_data.polls.clone(),
+            presentation_data.updates.clone(),
+            users.clone(),
+            presenters.clone(),
+        );
+
+        Ok(Self {
             id: presentation_id,
             presenter_identity,
-            users: Users::new(),
-            presenters: Arc::new(DashMap::new()),
+            users,
+            presenters,
             authentication_key,
-            ratelimiter,
-            slide_settings: Arc::new(None.into()),
+            authentication_key_source,
+            authentication_algorithms,
+            ratelimiter: Arc::new(Ratelimiter::new()),
+            slide_settings: Arc::new(slide_settings.into()),
             encrypted,
-            presentation_data: PresentationData::new(title),
-        }
+            journal: None,
+            presentation_data,
+            last_activity: Arc::new(AtomicU64::new(now_millis())),
+            ended: Arc::new(AtomicBool::new(false)),
+            poll_timer,
+        })
     }
 
     pub fn get_polls(&self) -> Polls {
         self.presentation_data.polls.clone()
     }
 
+    pub fn get_prompts(&self) -> Prompts {
+        self.presentation_data.prompts.clone()
+    }
+
+    pub fn get_questions(&self) -> Questions {
+        self.presentation_data.questions.clone()
+    }
+
+    pub fn get_engagement(&self) -> Engagement {
+        self.presentation_data.engagement.clone()
+    }
+
+    pub fn get_emoji_batcher(&self) -> EmojiBatcher {
+        self.presentation_data.emoji_batcher.clone()
+    }
+
+    pub fn get_updates(&self) -> Updates {
+        self.presentation_data.updates.clone()
+    }
+
+    pub fn get_resume_tokens(&self) -> ResumeTokens {
+        self.presentation_data.resume_tokens.clone()
+    }
+
+    pub fn get_poll_subscriptions(&self) -> PollSubscriptions {
+        self.presentation_data.poll_subscriptions.clone()
+    }
+
+    /// Have the poll timer close a poll once its deadline passes
+    pub fn time_poll(&self, poll_name: String) {
+        if self.poll_timer.send(poll_name).is_err() {
+            error!("The poll timer for [{}] is no longer running", self.id);
+        }
+    }
+
     pub fn get_title(&self) -> String {
         self.presentation_data.title.clone()
     }
+
+    /// The number of the slide the presenter is currently showing
+    pub fn current_slide(&self) -> u64 {
+        self.presentation_data.engagement.current_slide()
+    }
+
+    /// Note that someone is using the presentation so it isn't cleaned up
+    pub fn touch(&self) {
+        self.last_activity.store(now_millis(), Ordering::Relaxed);
+    }
+
+    pub fn end(&self) {
+        self.ended.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_ended(&self) -> bool {
+        self.ended.load(Ordering::SeqCst)
+    }
+
+    /// How many milliseconds it has been since anyone used the presentation
+    pub fn idle_for(&self) -> u64 {
+        now_millis().saturating_sub(self.last_activity.load(Ordering::Relaxed))
+    }
+
+    /// How many users have an open websocket
+    pub fn connected_users(&self) -> usize {
+        self.users.iter().filter(|user| user.sender.is_some()).count()
+    }
+
+    /// How many presenters have an open websocket
+    pub fn connected_presenters(&self) -> usize {
+        self.presenters
+            .iter()
+            .filter(|presenter| presenter.sender.is_some())
+            .count()
+    }
+
+    /// Whether any user or presenter has an open websocket
+    pub fn has_connections(&self) -> bool {
+        self.users.iter().any(|user| user.sender.is_some())
+            || self.presenters.iter().any(|presenter| presenter.sender.is_some())
+    }
+
+    /// Record the current audience size. Called whenever a user connects or leaves.
+    pub fn update_audience(&self) {
+        self.presentation_data
+            .engagement
+            .audience_changed(self.connected_users() as u64);
+    }
+
+    /// Send `Disconnect` to everyone connected and close their sockets
+    pub fn disconnect_everyone(&self, disconnection: &Disconnection) {
+        for mut user in self.users.iter_mut() {
+            user.disconnect(disconnection.clone());
+        }
+        for mut presenter in self.presenters.iter_mut() {
+            presenter.disconnect(disconnection.clone());
+        }
+    }
 }
@@ -1,12 +1,17 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     fmt::Display,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
 };
 
-use dashmap::DashMap;
+use dashmap::{mapref::entry::Entry, DashMap};
 use serde::{Deserialize, Serialize};
 
+use super::quiz::{self, LeaderboardEntry, QuizAnswer, QuizReveal};
+use super::runoff::{self, RunoffResults};
 use crate::NewPollMessage;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -19,114 +24,543 @@ pub enum VoteType {
     SingleValue { choice: String, value: u8 },
     /// Someone can vote for multiple choices with values between 0 and 255
     MultipleValue { choices: HashMap<String, u8> },
+    /// Someone ranks choices in order of preference, most preferred first.
+    /// Results are decided by instant-runoff.
+    Ranked { ranking: Vec<String> },
 }
 
-#[derive(Clone, Debug, Deserialize)]
+/// Controls what a user can do with their vote after it has been cast
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum VotePolicy {
+    /// Once a vote is cast it cannot be changed
+    #[default]
+    Immutable,
+    /// A vote can be replaced with a new one while the poll is open
+    Changeable,
+    /// A vote can be replaced or withdrawn entirely while the poll is open
+    Retractable,
+}
+
+/// Why a vote (or the retraction of one) was not accepted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VoteError {
+    NoSuchPoll,
+    Closed,
+    DeadlinePassed,
+    AlreadyVoted,
+    InvalidChoice,
+    NoChoices,
+    InvalidRanking,
+    WrongVoteType,
+    NotRetractable,
+    NotVoted,
+}
+
+impl Display for VoteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            Self::NoSuchPoll => "no poll with that name exists",
+            Self::Closed => "the poll is closed",
+            Self::DeadlinePassed => "the time to vote in this poll has run out",
+            Self::AlreadyVoted => "you have already voted and this poll does not allow changes",
+            Self::InvalidChoice => "the vote contains a choice that is not in the poll",
+            Self::NoChoices => "a vote must pick at least one choice",
+            Self::InvalidRanking => "a ranking must list at least one choice and each choice only once",
+            Self::WrongVoteType => "the vote type does not match the poll",
+            Self::NotRetractable => "this poll does not allow votes to be withdrawn",
+            Self::NotVoted => "you have not voted in this poll",
+        };
+        write!(f, "{reason}")
+    }
+}
+
+/// What happened to the poll when a vote was accepted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VoteOutcome {
+    /// This is the first vote this identity has cast in the poll
+    Recorded,
+    /// This vote replaced one the identity had previously cast
+    Replaced,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Vote {
     poll_name: String,
     vote_type: VoteType,
 }
 
+impl Vote {
+    pub fn poll_name(&self) -> &str {
+        &self.poll_name
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct IdentifiedVote {
     pub identity: String,
     pub vote: Vote,
 }
 
+/// The statistics for a single choice in a poll that are reported
+/// to presenters. Binary votes count as a value of 1.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChoiceTotals {
+    pub sum: u64,
+    pub count: u64,
+    pub min: u8,
+    pub max: u8,
+    pub mean: f64,
+}
+
+/// Running tally for a single choice in a poll. Every value that has been
+/// voted is kept as a histogram so the min and max are always exact.
+#[derive(Clone, Debug, Default)]
+struct ChoiceTally {
+    sum: u64,
+    count: u64,
+    values: BTreeMap<u8, u64>,
+}
+
+impl ChoiceTally {
+    fn add(&mut self, value: u8) {
+        self.sum += value as u64;
+        self.count += 1;
+        *self.values.entry(value).or_insert(0) += 1;
+    }
+
+    fn remove(&mut self, value: u8) {
+        if let Some(occurrences) = self.values.get_mut(&value) {
+            *occurrences -= 1;
+            if *occurrences == 0 {
+                self.values.remove(&value);
+            }
+            self.sum -= value as u64;
+            self.count -= 1;
+        }
+    }
+
+    fn totals(&self) -> ChoiceTotals {
+        ChoiceTotals {
+            sum: self.sum,
+            count: self.count,
+            min: self.values.keys().next().copied().unwrap_or(0),
+            max: self.values.keys().next_back().copied().unwrap_or(0),
+            mean: if self.count == 0 {
+                0.0
+            } else {
+                self.sum as f64 / self.count as f64
+            },
+        }
+    }
+}
+
+/// A vote along with when it was cast
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct CastVote {
+    vote_type: VoteType,
+    /// Milliseconds since the epoch
+    cast_at: u64,
+}
+
+/// Everything needed to rebuild a poll. Totals are not stored because
+/// they are recalculated from the votes.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PollSnapshot {
+    definition: NewPollMessage,
+    votes: HashMap<String, CastVote>,
+    open: bool,
+    deadline: u64,
+    created_at: u64,
+    revealed_at: u64,
+}
+
+tokio::task_local! {
+    /// The time of the journal event being replayed while a presentation is
+    /// rebuilt, so it is judged by when it originally happened
+    static REPLAYING_AT: u64;
+}
+
+/// Milliseconds since the unix epoch, used for poll deadlines. While an event
+/// is being replayed from a journal this is when the event happened.
+pub(crate) fn now_millis() -> u64 {
+    REPLAYING_AT.try_with(|millis| *millis).unwrap_or_else(|_| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|x| x.as_millis() as u64)
+            .unwrap_or(0)
+    })
+}
+
+/// Run `f` with the clock stopped at `millis` since the epoch. Tasks it
+/// spawns keep using the real time.
+pub(crate) async fn replaying_at<F: std::future::Future>(millis: u64, f: F) -> F::Output {
+    REPLAYING_AT.scope(millis, f).await
+}
+
 #[derive(Clone)]
 /// Structure that defines a poll and how its current state
 /// Votes are a u8 so that way users can have up to 255 different
 /// values to vote for while keeping sane tallying in totals which
 /// is a u64.
 pub struct Poll {
-    votes: Arc<DashMap<String, VoteType>>,
-    totals: Arc<DashMap<String, u64>>,
+    /// The message the presenter created the poll with
+    definition: NewPollMessage,
+    votes: Arc<DashMap<String, CastVote>>,
+    totals: Arc<DashMap<String, ChoiceTally>>,
     choices: HashSet<String>,
-    vote_type: VoteType,
+    /// Closed polls reject any new, changed or retracted votes
+    open: Arc<AtomicBool>,
+    /// When the poll stops accepting votes in milliseconds since the
+    /// epoch. Zero means the poll has no time limit.
+    deadline: Arc<AtomicU64>,
+    /// When the poll was created in milliseconds since the epoch
+    created_at: u64,
+    /// When the answer to a quiz poll was revealed in milliseconds since
+    /// the epoch. Zero means it has not been revealed.
+    revealed_at: Arc<AtomicU64>,
 }
 
 impl Poll {
-    pub fn new(choices: &[impl Display], vote_type: VoteType) -> Self {
+    pub fn new(definition: NewPollMessage) -> Self {
+        let created_at = now_millis();
+        let deadline = definition
+            .duration
+            .map(|seconds| created_at.saturating_add(seconds.saturating_mul(1000)))
+            .unwrap_or(0);
+
         Self {
             votes: Arc::new(DashMap::new()),
             totals: Arc::new(DashMap::new()),
-            choices: choices.into_iter().map(|x| x.to_string()).collect(),
-            vote_type,
+            choices: definition.options.iter().map(|x| x.to_string()).collect(),
+            open: Arc::new(AtomicBool::new(true)),
+            deadline: Arc::new(AtomicU64::new(deadline)),
+            created_at,
+            revealed_at: Arc::new(AtomicU64::new(0)),
+            definition,
         }
     }
 
-    pub fn vote(&self, vote: IdentifiedVote) -> bool {
-        // If the user has already voted, don't let them do so again
-        if self.votes.contains_key(vote.identity.as_str()) {
-            warn!(
-                "[{}] already voted for in [{}]",
-                vote.identity, vote.vote.poll_name
-            );
-            return false;
+    pub fn definition(&self) -> &NewPollMessage {
+        &self.definition
+    }
+
+    /// How many identities have a vote in the poll
+    pub fn voters(&self) -> u64 {
+        self.votes.len() as u64
+    }
+
+    /// When the poll was created, in milliseconds since the epoch
+    pub fn created_at(&self) -> u64 {
+        self.created_at
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open.load(Ordering::SeqCst)
+    }
+
+    /// Set whether the poll is accepting votes. Reopening a poll removes
+    /// any time limit it had.
+    pub fn set_open(&self, open: bool) {
+        if open {
+            self.deadline.store(0, Ordering::SeqCst);
         }
+        self.open.store(open, Ordering::SeqCst);
+    }
 
-        // Ensure vote type is correct
-        // is there a better way to do this?
-        match (&self.vote_type, &vote.vote.vote_type) {
-            (VoteType::SingleBinary { .. }, VoteType::SingleBinary { choice }) => {
-                if !self.choices.contains(choice) {
-                    warn!(
-                        "[{}] tried to vote in poll with an invalid choice: [{}]",
-                        vote.identity, choice
-                    );
-                    return false;
-                }
+    /// The deadline of the poll in milliseconds since the epoch if it has one
+    pub fn deadline(&self) -> Option<u64> {
+        match self.deadline.load(Ordering::SeqCst) {
+            0 => None,
+            deadline => Some(deadline),
+        }
+    }
 
-                self.votes
-                    .insert(vote.identity.clone(), vote.vote.vote_type.clone());
-                // This is possible to deadlock if we ever hold other references.
-                // So let's never do that.
-                if self.totals.contains_key(choice) {
-                    self.totals.alter(choice, |_, x| x + 1);
-                } else {
-                    self.totals.insert(choice.clone(), 1);
-                }
-                info!(
-                    "[{}] voted for [{}] in [{}]",
-                    vote.identity, choice, vote.vote.poll_name
-                );
+    /// Make sure the poll is still accepting votes
+    fn check_accepting(&self) -> Result<(), VoteError> {
+        if !self.is_open() {
+            return Err(VoteError::Closed);
+        }
+
+        match self.deadline() {
+            Some(deadline) if now_millis() >= deadline => Err(VoteError::DeadlinePassed),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn totals(&self) -> HashMap<String, ChoiceTotals> {
+        self.totals
+            .iter()
+            .map(|x| (x.key().to_string(), x.value().totals()))
+            .collect()
+    }
+
+    /// Add a value to the tally for a choice
+    fn tally(&self, choice: &str, value: u8) {
+        // This is possible to deadlock if we ever hold other references.
+        // So let's never do that.
+        self.totals
+            .entry(choice.to_string())
+            .or_default()
+            .add(value);
+    }
+
+    /// Remove a value that was previously added to the tally for a choice
+    fn untally(&self, choice: &str, value: u8) {
+        if let Some(mut tally) = self.totals.get_mut(choice) {
+            tally.remove(value);
+        }
+    }
+
+    /// The values a vote adds to each choice it picked. Binary votes
+    /// contribute a value of 1 to every choice that was picked. Ranked
+    /// votes contribute the position each choice was ranked at.
+    fn contributions(vote_type: &VoteType) -> Vec<(&String, u8)> {
+        match vote_type {
+            VoteType::SingleBinary { choice } => vec![(choice, 1)],
+            VoteType::MultipleBinary { choices } => choices
+                .iter()
+                .filter(|(_, picked)| **picked)
+                .map(|(choice, _)| (choice, 1))
+                .collect(),
+            VoteType::SingleValue { choice, value } => vec![(choice, *value)],
+            VoteType::MultipleValue { choices } => choices
+                .iter()
+                .map(|(choice, value)| (choice, *value))
+                .collect(),
+            // Rankings are never longer than 255 so the position always fits
+            VoteType::Ranked { ranking } => ranking
+                .iter()
+                .enumerate()
+                .map(|(position, choice)| (choice, (position + 1) as u8))
+                .collect(),
+        }
+    }
+
+    /// Check that every choice in a vote is one this poll offers
+    fn valid_choices<'a>(
+        &self,
+        identity: &str,
+        mut choices: impl Iterator<Item = &'a String>,
+    ) -> Result<(), VoteError> {
+        match choices.find(|choice| !self.choices.contains(*choice)) {
+            Some(choice) => {
+                warn!("[{identity}] tried to vote in poll with an invalid choice: [{choice}]");
+                Err(VoteError::InvalidChoice)
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn vote(&self, vote: IdentifiedVote) -> Result<VoteOutcome, VoteError> {
+        let identity = vote.identity;
+        let poll_name = vote.vote.poll_name;
+        let vote_type = vote.vote.vote_type;
+
+        self.check_accepting()?;
+
+        // Ensure vote type is correct and only contains choices from this poll
+        match (&self.definition.vote_type, &vote_type) {
+            (VoteType::SingleBinary { .. }, VoteType::SingleBinary { choice })
+            | (VoteType::SingleValue { .. }, VoteType::SingleValue { choice, .. }) => {
+                self.valid_choices(&identity, std::iter::once(choice))?
             }
             (VoteType::MultipleBinary { .. }, VoteType::MultipleBinary { choices }) => {
-                for choice in choices.iter() {
-                    if !self.choices.contains(choice.0) {
-                        warn!(
-                            "[{}] tried to vote in poll with an invalid choice: [{}]",
-                            vote.identity, choice.0
-                        );
-                        return false;
-                    }
+                self.valid_choices(&identity, choices.keys())?;
+                // Picking nothing would count as a voter without adding to any choice
+                if !choices.values().any(|picked| *picked) {
+                    warn!("[{identity}] tried to vote without picking any choices");
+                    return Err(VoteError::NoChoices);
                 }
-
-                self.votes
-                    .insert(vote.identity, vote.vote.vote_type.clone());
-                // This is possible to deadlock if we ever hold other references.
-                // So let's never do that.
-                for (choice, _) in choices.into_iter().filter(|(_, picked)| **picked) {
-                    if self.totals.contains_key(choice) {
-                        self.totals.alter(choice, |_, x| x + 1);
-                    } else {
-                        self.totals.insert(choice.clone(), 1);
-                    }
+            }
+            (VoteType::MultipleValue { .. }, VoteType::MultipleValue { choices }) => {
+                self.valid_choices(&identity, choices.keys())?;
+                if choices.is_empty() {
+                    warn!("[{identity}] tried to vote without picking any choices");
+                    return Err(VoteError::NoChoices);
+                }
+            }
+            (VoteType::Ranked { .. }, VoteType::Ranked { ranking }) => {
+                self.valid_choices(&identity, ranking.iter())?;
+                let unique: HashSet<&String> = ranking.iter().collect();
+                if ranking.is_empty()
+                    || ranking.len() > u8::MAX as usize
+                    || unique.len() != ranking.len()
+                {
+                    warn!("[{identity}] tried to vote with an invalid ranking: {ranking:?}");
+                    return Err(VoteError::InvalidRanking);
                 }
             }
-            // TODO @obelisk: Implement the rest of these
-            (VoteType::SingleValue { .. }, VoteType::SingleValue { .. }) => return false,
-            (VoteType::MultipleValue { .. }, VoteType::MultipleValue { .. }) => return false,
             _ => {
                 warn!(
                     "{} tried to vote for a poll with the wrong vote type: [{:?}] vs [{:?}]",
-                    vote.identity, self.vote_type, vote.vote.vote_type
+                    identity, self.definition.vote_type, vote_type
                 );
-                return false;
+                return Err(VoteError::WrongVoteType);
+            }
+        };
+
+        // Holding the entry keeps another vote from the same identity from
+        // racing us while the totals are updated. Totals are only ever locked
+        // after votes so this cannot deadlock.
+        let cast_vote = CastVote {
+            vote_type: vote_type.clone(),
+            cast_at: now_millis(),
+        };
+        let outcome = match self.votes.entry(identity.clone()) {
+            Entry::Occupied(mut existing) => {
+                // If the user has already voted, only let them do so again if
+                // the poll allows it
+                if self.definition.vote_policy == VotePolicy::Immutable {
+                    warn!("[{identity}] already voted for in [{poll_name}]");
+                    return Err(VoteError::AlreadyVoted);
+                }
+
+                let previous = existing.insert(cast_vote);
+                for (choice, value) in Self::contributions(&previous.vote_type) {
+                    self.untally(choice, value);
+                }
+                for (choice, value) in Self::contributions(&vote_type) {
+                    self.tally(choice, value);
+                }
+                VoteOutcome::Replaced
+            }
+            Entry::Vacant(entry) => {
+                for (choice, value) in Self::contributions(&vote_type) {
+                    self.tally(choice, value);
+                }
+                entry.insert(cast_vote);
+                VoteOutcome::Recorded
+            }
+        };
+
+        info!("[{identity}] voted {vote_type:?} in [{poll_name}] ({outcome:?})");
+        Ok(outcome)
+    }
+
+    /// Withdraw the vote an identity has cast, removing its contribution
+    /// from the totals. Only allowed if the poll is retractable.
+    pub fn retract(&self, identity: &str) -> Result<(), VoteError> {
+        self.check_accepting()?;
+
+        if self.definition.vote_policy != VotePolicy::Retractable {
+            warn!("[{identity}] tried to retract a vote in a poll that does not allow it");
+            return Err(VoteError::NotRetractable);
+        }
+
+        match self.votes.entry(identity.to_string()) {
+            Entry::Occupied(existing) => {
+                for (choice, value) in Self::contributions(&existing.get().vote_type) {
+                    self.untally(choice, value);
+                }
+                existing.remove();
+                Ok(())
+            }
+            Entry::Vacant(_) => {
+                warn!("[{identity}] tried to retract a vote but has not voted");
+                Err(VoteError::NotVoted)
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> PollSnapshot {
+        PollSnapshot {
+            definition: self.definition.clone(),
+            votes: self
+                .votes
+                .iter()
+                .map(|x| (x.key().clone(), x.value().clone()))
+                .collect(),
+            open: self.is_open(),
+            deadline: self.deadline.load(Ordering::SeqCst),
+            created_at: self.created_at,
+            revealed_at: self.revealed_at.load(Ordering::SeqCst),
+        }
+    }
+
+    pub fn restore(snapshot: PollSnapshot) -> Self {
+        let definition = snapshot.definition;
+        let poll = Self {
+            votes: Arc::new(DashMap::new()),
+            totals: Arc::new(DashMap::new()),
+            choices: definition.options.iter().map(|x| x.to_string()).collect(),
+            open: Arc::new(AtomicBool::new(snapshot.open)),
+            deadline: Arc::new(AtomicU64::new(snapshot.deadline)),
+            created_at: snapshot.created_at,
+            revealed_at: Arc::new(AtomicU64::new(snapshot.revealed_at)),
+            definition,
+        };
+
+        for (identity, vote) in snapshot.votes {
+            for (choice, value) in Self::contributions(&vote.vote_type) {
+                poll.tally(choice, value);
             }
+            poll.votes.insert(identity, vote);
+        }
+
+        poll
+    }
+
+    /// Run an instant-runoff tally over every ranked vote. Returns None if
+    /// this is not a ranked poll.
+    pub fn runoff(&self) -> Option<RunoffResults> {
+        if !matches!(self.definition.vote_type, VoteType::Ranked { .. }) {
+            return None;
+        }
+
+        let ballots: Vec<Vec<String>> = self
+            .votes
+            .iter()
+            .filter_map(|vote| match &vote.vote_type {
+                VoteType::Ranked { ranking } => Some(ranking.clone()),
+                _ => None,
+            })
+            .collect();
+
+        Some(runoff::instant_runoff(&self.choices, &ballots))
+    }
+
+    pub fn is_revealed(&self) -> bool {
+        self.revealed_at.load(Ordering::SeqCst) != 0
+    }
+
+    /// Score every vote in a quiz poll whose answer has been revealed. Returns
+    /// None if the poll is not a quiz or the answer is still secret.
+    pub fn quiz_reveal(&self) -> Option<QuizReveal> {
+        let settings = self.definition.quiz.as_ref()?;
+        let revealed_at = match self.revealed_at.load(Ordering::SeqCst) {
+            0 => return None,
+            revealed_at => revealed_at,
         };
 
-        true
+        // Speed is measured against the time limit if there is one, otherwise
+        // against how long the question was up before the reveal
+        let window = match self.definition.duration {
+            Some(seconds) => seconds.saturating_mul(1000),
+            None => revealed_at.saturating_sub(self.created_at),
+        };
+
+        let answers = self
+            .votes
+            .iter()
+            .map(|vote| {
+                let picked = Self::contributions(&vote.vote_type)
+                    .into_iter()
+                    .map(|(choice, _)| choice)
+                    .collect();
+                let correct = settings.is_correct(&picked);
+                let points = if correct {
+                    settings.score(vote.cast_at.saturating_sub(self.created_at), window)
+                } else {
+                    0
+                };
+                (vote.key().clone(), QuizAnswer { correct, points })
+            })
+            .collect();
+
+        Some(QuizReveal {
+            correct: settings.correct.clone(),
+            answers,
+        })
     }
 }
 
@@ -135,6 +569,12 @@ pub struct Polls {
     polls: Arc<DashMap<String, Poll>>,
 }
 
+impl Default for Polls {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Polls {
     pub fn new() -> Self {
         Self {
@@ -142,42 +582,345 @@ impl Polls {
         }
     }
 
-    pub fn new_poll(&self, pole: NewPollMessage) -> Result<(), NewPollMessage> {
+    pub fn new_poll(&self, pole: NewPollMessage) -> Result<(), Box<NewPollMessage>> {
         if let Some(existing_pole) = self.polls.get(&pole.name) {
-            let existing_pole = existing_pole.value().clone();
-            Err(NewPollMessage {
-                name: pole.name.clone(),
-                options: existing_pole.choices.into_iter().collect(),
-                vote_type: existing_pole.vote_type,
-            })
+            Err(Box::new(existing_pole.value().definition.clone()))
         } else {
-            self.polls
-                .insert(pole.name, Poll::new(&pole.options, pole.vote_type));
+            self.polls.insert(pole.name.clone(), Poll::new(pole));
             Ok(())
         }
     }
 
-    pub fn vote_in_poll(&self, vote: IdentifiedVote) -> Result<(), String> {
-        let vote_name = vote.vote.poll_name.clone();
-        let identity = vote.identity.clone();
-        match self
+    pub fn get_poll(&self, poll_name: &str) -> Option<Poll> {
+        self.polls.get(poll_name).map(|poll| poll.value().clone())
+    }
+
+    /// Stop a poll from accepting votes. The votes already cast are kept.
+    pub fn close_poll(&self, poll_name: &str) -> Result<(), String> {
+        self.set_poll_open(poll_name, false)
+    }
+
+    /// Let a closed poll accept votes again
+    pub fn reopen_poll(&self, poll_name: &str) -> Result<(), String> {
+        self.set_poll_open(poll_name, true)
+    }
+
+    fn set_poll_open(&self, poll_name: &str, open: bool) -> Result<(), String> {
+        let poll = self
+            .polls
+            .get(poll_name)
+            .ok_or(format!("No poll with name {} exists", poll_name))?;
+        // Once the answer is out, letting people vote again would give away points
+        if open && poll.is_revealed() {
+            return Err(format!(
+                "The answer to {} has been revealed so it cannot be reopened",
+                poll_name
+            ));
+        }
+        poll.set_open(open);
+        Ok(())
+    }
+
+    /// Close a poll because its time limit has been reached. The poll is only
+    /// closed if it is still open and still has the deadline that was scheduled,
+    /// otherwise it has been closed, reopened or replaced since.
+    pub fn expire_poll(&self, poll_name: &str, deadline: u64) -> Option<Poll> {
+        let poll = self.polls.get(poll_name)?;
+        if !poll.is_open() || poll.deadline() != Some(deadline) {
+            return None;
+        }
+        poll.set_open(false);
+        Some(poll.value().clone())
+    }
+
+    /// Close a quiz poll and score its votes so users can be told how they did.
+    /// Revealing the same poll again gives back the same results.
+    pub fn reveal_quiz(&self, poll_name: &str) -> Result<QuizReveal, String> {
+        let poll = self
             .polls
+            .get(poll_name)
+            .ok_or(format!("No poll with name {} exists", poll_name))?;
+        if poll.definition.quiz.is_none() {
+            return Err(format!("Poll {} is not a quiz", poll_name));
+        }
+
+        poll.set_open(false);
+        let _ = poll.revealed_at.compare_exchange(
+            0,
+            now_millis(),
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        );
+
+        poll.quiz_reveal()
+            .ok_or(format!("Could not reveal the answer to {}", poll_name))
+    }
+
+    /// Rank every identity by the points they have earned across all of
+    /// the revealed quiz polls in the presentation
+    pub fn leaderboard(&self) -> Vec<LeaderboardEntry> {
+        let reveals: Vec<QuizReveal> = self
+            .polls
+            .iter()
+            .filter_map(|poll| poll.value().quiz_reveal())
+            .collect();
+        quiz::leaderboard(reveals.into_iter())
+    }
+
+    /// Remove a poll and all of its votes so the name can be used again
+    pub fn delete_poll(&self, poll_name: &str) -> Result<(), String> {
+        self.polls
+            .remove(poll_name)
+            .map(|_| ())
+            .ok_or(format!("No poll with name {} exists", poll_name))
+    }
+
+    pub fn vote_in_poll(&self, vote: IdentifiedVote) -> Result<VoteOutcome, VoteError> {
+        self.polls
             .get(&vote.vote.poll_name)
-            .map(|poll| poll.vote(vote))
-        {
-            None => Err(format!("No poll with name {} exists", &vote_name)),
-            Some(false) => Err(format!("{} could not vote in {}", identity, &vote_name)),
-            Some(true) => Ok(()),
+            .ok_or(VoteError::NoSuchPoll)?
+            .vote(vote)
+    }
+
+    pub fn retract_vote(&self, identity: &str, poll_name: &str) -> Result<(), VoteError> {
+        self.polls
+            .get(poll_name)
+            .ok_or(VoteError::NoSuchPoll)?
+            .retract(identity)
+    }
+
+    pub fn snapshot(&self) -> Vec<PollSnapshot> {
+        self.polls.iter().map(|poll| poll.value().snapshot()).collect()
+    }
+
+    pub fn restore(snapshots: Vec<PollSnapshot>) -> Self {
+        let polls = Self::new();
+        for snapshot in snapshots {
+            let poll = Poll::restore(snapshot);
+            polls.polls.insert(poll.definition.name.clone(), poll);
         }
+        polls
     }
 
-    pub fn get_poll_totals(&self, pole_name: &str) -> Option<HashMap<String, u64>> {
-        self.polls.get(pole_name).map(|poll| {
-            poll.value()
-                .totals
-                .iter()
-                .map(|x| (x.key().to_string(), *x.value()))
-                .collect()
+    /// Every poll in the order they were created
+    pub fn all(&self) -> Vec<Poll> {
+        let mut polls: Vec<Poll> = self.polls.iter().map(|x| x.value().clone()).collect();
+        polls.sort_by_key(|poll| poll.created_at());
+        polls
+    }
+
+    /// The names of the polls that are open and have a deadline
+    pub fn timed_polls(&self) -> Vec<String> {
+        self.polls
+            .iter()
+            .filter(|poll| poll.is_open() && poll.deadline().is_some())
+            .map(|poll| poll.key().clone())
+            .collect()
+    }
+
+    pub fn get_poll_totals(&self, pole_name: &str) -> Option<HashMap<String, ChoiceTotals>> {
+        self.polls.get(pole_name).map(|poll| poll.value().totals())
+    }
+
+    pub fn get_poll_runoff(&self, pole_name: &str) -> Option<RunoffResults> {
+        self.polls.get(pole_name).and_then(|poll| poll.value().runoff())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poll(vote_type: VoteType, vote_policy: VotePolicy) -> Poll {
+        Poll::new(NewPollMessage {
+            name: "poll".to_string(),
+            options: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            vote_type,
+            vote_policy,
+            duration: None,
+            share_results: false,
+            quiz: None,
         })
     }
+
+    fn vote(poll: &Poll, identity: &str, vote_type: VoteType) -> Result<VoteOutcome, VoteError> {
+        poll.vote(IdentifiedVote {
+            identity: identity.to_string(),
+            vote: Vote {
+                poll_name: "poll".to_string(),
+                vote_type,
+            },
+        })
+    }
+
+    /// (sum, count) for a choice, treating one nobody voted for as empty
+    fn total(poll: &Poll, choice: &str) -> (u64, u64) {
+        poll.totals()
+            .get(choice)
+            .map(|x| (x.sum, x.count))
+            .unwrap_or((0, 0))
+    }
+
+    fn single_binary(choice: &str) -> VoteType {
+        VoteType::SingleBinary { choice: choice.to_string() }
+    }
+
+    fn multiple_binary(choices: &[(&str, bool)]) -> VoteType {
+        VoteType::MultipleBinary {
+            choices: choices.iter().map(|(x, picked)| (x.to_string(), *picked)).collect(),
+        }
+    }
+
+    fn single_value(choice: &str, value: u8) -> VoteType {
+        VoteType::SingleValue { choice: choice.to_string(), value }
+    }
+
+    fn multiple_value(choices: &[(&str, u8)]) -> VoteType {
+        VoteType::MultipleValue {
+            choices: choices.iter().map(|(x, value)| (x.to_string(), *value)).collect(),
+        }
+    }
+
+    fn ranked(ranking: &[&str]) -> VoteType {
+        VoteType::Ranked {
+            ranking: ranking.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn changing_a_single_binary_vote_moves_it() {
+        let poll = poll(single_binary("a"), VotePolicy::Changeable);
+        assert_eq!(vote(&poll, "u", single_binary("a")), Ok(VoteOutcome::Recorded));
+        assert_eq!(vote(&poll, "u", single_binary("b")), Ok(VoteOutcome::Replaced));
+
+        assert_eq!(total(&poll, "a"), (0, 0));
+        assert_eq!(total(&poll, "b"), (1, 1));
+        assert_eq!(poll.voters(), 1);
+    }
+
+    #[test]
+    fn changing_a_multiple_binary_vote_only_counts_picked_choices() {
+        let poll = poll(multiple_binary(&[]), VotePolicy::Changeable);
+        vote(&poll, "u", multiple_binary(&[("a", true), ("b", true), ("c", false)])).unwrap();
+        vote(&poll, "u", multiple_binary(&[("a", false), ("c", true)])).unwrap();
+
+        assert_eq!(total(&poll, "a"), (0, 0));
+        assert_eq!(total(&poll, "b"), (0, 0));
+        assert_eq!(total(&poll, "c"), (1, 1));
+    }
+
+    #[test]
+    fn changing_a_single_value_vote_keeps_min_and_max_exact() {
+        let poll = poll(single_value("a", 0), VotePolicy::Changeable);
+        vote(&poll, "u", single_value("a", 200)).unwrap();
+        vote(&poll, "v", single_value("a", 10)).unwrap();
+        vote(&poll, "u", single_value("a", 50)).unwrap();
+
+        let totals = &poll.totals()["a"];
+        assert_eq!((totals.sum, totals.count), (60, 2));
+        assert_eq!((totals.min, totals.max), (10, 50));
+    }
+
+    #[test]
+    fn changing_a_multiple_value_vote_replaces_every_value() {
+        let poll = poll(multiple_value(&[]), VotePolicy::Changeable);
+        vote(&poll, "u", multiple_value(&[("a", 5), ("b", 7)])).unwrap();
+        vote(&poll, "u", multiple_value(&[("b", 1), ("c", 255)])).unwrap();
+
+        assert_eq!(total(&poll, "a"), (0, 0));
+        assert_eq!(total(&poll, "b"), (1, 1));
+        assert_eq!(total(&poll, "c"), (255, 1));
+    }
+
+    #[test]
+    fn changing_a_ranked_vote_changes_the_runoff() {
+        let poll = poll(ranked(&[]), VotePolicy::Changeable);
+        vote(&poll, "u", ranked(&["a", "b"])).unwrap();
+        vote(&poll, "v", ranked(&["a"])).unwrap();
+        vote(&poll, "w", ranked(&["b"])).unwrap();
+        assert_eq!(poll.runoff().unwrap().winner.as_deref(), Some("a"));
+
+        vote(&poll, "u", ranked(&["b", "a"])).unwrap();
+        assert_eq!(poll.runoff().unwrap().winner.as_deref(), Some("b"));
+        // Positions are tallied, first place counts as 1
+        assert_eq!(total(&poll, "a"), (3, 2));
+        assert_eq!(total(&poll, "b"), (2, 2));
+    }
+
+    #[test]
+    fn retracting_removes_the_vote_for_every_vote_type() {
+        let cases = [
+            single_binary("a"),
+            multiple_binary(&[("a", true), ("b", true)]),
+            single_value("a", 9),
+            multiple_value(&[("a", 3), ("c", 4)]),
+            ranked(&["c", "a", "b"]),
+        ];
+
+        for vote_type in cases {
+            let poll = poll(vote_type.clone(), VotePolicy::Retractable);
+            vote(&poll, "u", vote_type.clone()).unwrap();
+            assert_eq!(poll.retract("u"), Ok(()), "{vote_type:?}");
+
+            assert_eq!(poll.voters(), 0, "{vote_type:?}");
+            for choice in ["a", "b", "c"] {
+                assert_eq!(total(&poll, choice), (0, 0), "{vote_type:?}");
+            }
+            assert_eq!(poll.retract("u"), Err(VoteError::NotVoted), "{vote_type:?}");
+
+            // Voting again after retracting counts as a new vote
+            assert_eq!(vote(&poll, "u", vote_type.clone()), Ok(VoteOutcome::Recorded));
+        }
+    }
+
+    #[test]
+    fn policies_limit_changes_and_retractions() {
+        let immutable = poll(single_binary("a"), VotePolicy::Immutable);
+        vote(&immutable, "u", single_binary("a")).unwrap();
+        assert_eq!(vote(&immutable, "u", single_binary("b")), Err(VoteError::AlreadyVoted));
+        assert_eq!(immutable.retract("u"), Err(VoteError::NotRetractable));
+        assert_eq!(total(&immutable, "a"), (1, 1));
+
+        let changeable = poll(single_binary("a"), VotePolicy::Changeable);
+        vote(&changeable, "u", single_binary("a")).unwrap();
+        assert_eq!(changeable.retract("u"), Err(VoteError::NotRetractable));
+    }
+
+    #[test]
+    fn closed_polls_reject_changes_and_retractions() {
+        let poll = poll(single_binary("a"), VotePolicy::Retractable);
+        vote(&poll, "u", single_binary("a")).unwrap();
+        poll.set_open(false);
+
+        assert_eq!(vote(&poll, "u", single_binary("b")), Err(VoteError::Closed));
+        assert_eq!(poll.retract("u"), Err(VoteError::Closed));
+        assert_eq!(total(&poll, "a"), (1, 1));
+    }
+
+    #[test]
+    fn invalid_votes_are_rejected_without_touching_the_totals() {
+        let poll = poll(ranked(&[]), VotePolicy::Changeable);
+        vote(&poll, "u", ranked(&["a"])).unwrap();
+
+        assert_eq!(vote(&poll, "u", ranked(&["a", "a"])), Err(VoteError::InvalidRanking));
+        assert_eq!(vote(&poll, "u", ranked(&[])), Err(VoteError::InvalidRanking));
+        assert_eq!(vote(&poll, "u", ranked(&["z"])), Err(VoteError::InvalidChoice));
+        assert_eq!(vote(&poll, "u", single_binary("a")), Err(VoteError::WrongVoteType));
+        assert_eq!(total(&poll, "a"), (1, 1));
+    }
+
+    #[test]
+    fn votes_without_any_choices_are_rejected() {
+        let binary = poll(multiple_binary(&[]), VotePolicy::Immutable);
+        assert_eq!(vote(&binary, "u", multiple_binary(&[])), Err(VoteError::NoChoices));
+        assert_eq!(
+            vote(&binary, "u", multiple_binary(&[("a", false)])),
+            Err(VoteError::NoChoices)
+        );
+        assert_eq!(binary.voters(), 0);
+
+        let value = poll(multiple_value(&[]), VotePolicy::Immutable);
+        assert_eq!(vote(&value, "u", multiple_value(&[])), Err(VoteError::NoChoices));
+        assert_eq!(value.voters(), 0);
+    }
 }
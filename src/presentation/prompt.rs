@@ -0,0 +1,403 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::{encryption, NewPromptMessage};
+
+/// The longest response allowed if the prompt doesn't set its own limit
+pub const DEFAULT_MAX_RESPONSE_LENGTH: usize = 64;
+
+/// The longest response any prompt can allow
+pub const MAX_RESPONSE_LENGTH: usize = 280;
+
+/// Common English words that don't say much in a word cloud
+const STOP_WORDS: &[&str] = &[
+    "a", "about", "an", "and", "are", "as", "at", "be", "but", "by", "for", "from", "has", "have",
+    "i", "in", "is", "it", "its", "me", "my", "not", "of", "on", "or", "so", "that", "the", "this",
+    "to", "too", "very", "was", "we", "were", "with", "you", "your",
+];
+
+/// Turn a response into the words that are counted for the word cloud. Words
+/// are case folded and stripped of surrounding punctuation. A word is only
+/// counted once per response.
+pub fn normalize(text: &str, remove_stop_words: bool) -> Vec<String> {
+    let mut seen = HashSet::new();
+    text.split_whitespace()
+        .map(|word| {
+            word.trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase()
+        })
+        .filter(|word| !word.is_empty())
+        .filter(|word| !remove_stop_words || !STOP_WORDS.contains(&word.as_str()))
+        .filter(|word| seen.insert(word.clone()))
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum ResponseState {
+    /// Waiting for a presenter to approve or reject it
+    Pending,
+    /// Counted in the word cloud
+    Approved,
+    /// Will never be counted
+    Rejected,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TextResponse {
+    pub id: u64,
+    pub identity: String,
+    pub text: String,
+    pub state: ResponseState,
+    words: Vec<String>,
+}
+
+/// An approved response as it is sent to presenters
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ApprovedResponse {
+    pub id: u64,
+    pub identity: String,
+    pub text: String,
+}
+
+impl From<&TextResponse> for ApprovedResponse {
+    fn from(response: &TextResponse) -> Self {
+        Self {
+            id: response.id,
+            identity: response.identity.clone(),
+            text: response.text.clone(),
+        }
+    }
+}
+
+/// Everything needed to rebuild a prompt. The word cloud is not stored
+/// because it is recounted from the approved responses.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PromptSnapshot {
+    definition: NewPromptMessage,
+    moderated: bool,
+    responses: Vec<TextResponse>,
+    next_id: u64,
+}
+
+/// A free text question from the presenter and everyone's answers to it
+#[derive(Clone)]
+pub struct Prompt {
+    definition: NewPromptMessage,
+    /// Moderated prompts hold responses until a presenter approves them
+    moderated: Arc<AtomicBool>,
+    /// Maps user identity to their response. Each identity gets one response.
+    responses: Arc<DashMap<String, TextResponse>>,
+    /// How many approved responses contained each word
+    words: Arc<DashMap<String, u64>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Prompt {
+    pub fn new(definition: NewPromptMessage) -> Self {
+        Self {
+            moderated: Arc::new(AtomicBool::new(definition.moderated)),
+            responses: Arc::new(DashMap::new()),
+            words: Arc::new(DashMap::new()),
+            next_id: Arc::new(AtomicU64::new(0)),
+            definition,
+        }
+    }
+
+    pub fn max_length(&self) -> usize {
+        self.definition
+            .max_length
+            .unwrap_or(DEFAULT_MAX_RESPONSE_LENGTH)
+            .min(MAX_RESPONSE_LENGTH)
+    }
+
+    fn count_words(&self, words: &[String]) {
+        for word in words {
+            *self.words.entry(word.clone()).or_insert(0) += 1;
+        }
+    }
+
+    /// Record a user's response. Returns the response so the caller can tell
+    /// whether it was counted or is waiting on moderation. Encrypted responses
+    /// are stored as they are and left out of the word cloud.
+    pub fn respond(
+        &self,
+        identity: &str,
+        text: &str,
+        encrypted: bool,
+    ) -> Result<TextResponse, String> {
+        let text = text.trim();
+        let length = if encrypted {
+            encryption::check_sealed("Responses", text)?
+        } else {
+            text.chars().count()
+        };
+        // Sealed content can be empty too so check what it holds
+        if length == 0 {
+            return Err("Responses cannot be empty".to_string());
+        }
+        if length > self.max_length() {
+            return Err(format!(
+                "Responses can be at most {} {}",
+                self.max_length(),
+                if encrypted { "bytes" } else { "characters" }
+            ));
+        }
+
+        let entry = match self.responses.entry(identity.to_string()) {
+            Entry::Occupied(_) => return Err("You have already responded".to_string()),
+            Entry::Vacant(entry) => entry,
+        };
+
+        let state = if self.moderated.load(Ordering::SeqCst) {
+            ResponseState::Pending
+        } else {
+            ResponseState::Approved
+        };
+        let response = TextResponse {
+            id: self.next_id.fetch_add(1, Ordering::SeqCst),
+            identity: identity.to_string(),
+            text: text.to_string(),
+            state,
+            words: if encrypted {
+                Vec::new()
+            } else {
+                normalize(text, self.definition.remove_stop_words)
+            },
+        };
+
+        if state == ResponseState::Approved {
+            self.count_words(&response.words);
+        }
+        entry.insert(response.clone());
+
+        Ok(response)
+    }
+
+    pub fn set_moderated(&self, moderated: bool) {
+        self.moderated.store(moderated, Ordering::SeqCst);
+    }
+
+    /// Approve or reject a response that is waiting on moderation. Returns
+    /// the response after it has been moderated.
+    pub fn moderate(&self, id: u64, approve: bool) -> Result<TextResponse, String> {
+        let mut response = self
+            .responses
+            .iter_mut()
+            .find(|response| response.id == id)
+            .ok_or(format!("No response with id {id} exists"))?;
+
+        if response.state != ResponseState::Pending {
+            return Err(format!("Response {id} has already been moderated"));
+        }
+
+        if approve {
+            response.state = ResponseState::Approved;
+            self.count_words(&response.words);
+        } else {
+            response.state = ResponseState::Rejected;
+        }
+        Ok(response.clone())
+    }
+
+    pub fn snapshot(&self) -> PromptSnapshot {
+        PromptSnapshot {
+            definition: self.definition.clone(),
+            moderated: self.moderated.load(Ordering::SeqCst),
+            responses: self.responses.iter().map(|x| x.value().clone()).collect(),
+            next_id: self.next_id.load(Ordering::SeqCst),
+        }
+    }
+
+    pub fn restore(snapshot: PromptSnapshot) -> Self {
+        let prompt = Self::new(snapshot.definition);
+        prompt.set_moderated(snapshot.moderated);
+        prompt.next_id.store(snapshot.next_id, Ordering::SeqCst);
+        for response in snapshot.responses {
+            if response.state == ResponseState::Approved {
+                prompt.count_words(&response.words);
+            }
+            prompt.responses.insert(response.identity.clone(), response);
+        }
+        prompt
+    }
+
+    /// Every approved response in the order they were made
+    pub fn approved_responses(&self) -> Vec<ApprovedResponse> {
+        let mut approved: Vec<ApprovedResponse> = self
+            .responses
+            .iter()
+            .filter(|x| x.state == ResponseState::Approved)
+            .map(|x| ApprovedResponse::from(x.value()))
+            .collect();
+        approved.sort_by_key(|x| x.id);
+        approved
+    }
+
+    /// The number of approved responses containing each word
+    pub fn word_cloud(&self) -> HashMap<String, u64> {
+        self.words
+            .iter()
+            .map(|x| (x.key().clone(), *x.value()))
+            .collect()
+    }
+}
+
+#[derive(Clone)]
+pub struct Prompts {
+    prompts: Arc<DashMap<String, Prompt>>,
+}
+
+impl Default for Prompts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Prompts {
+    pub fn new() -> Self {
+        Self {
+            prompts: Arc::new(DashMap::new()),
+        }
+    }
+
+    pub fn new_prompt(&self, prompt: NewPromptMessage) -> Result<(), String> {
+        match self.prompts.entry(prompt.name.clone()) {
+            Entry::Occupied(_) => Err(format!("A prompt named {} already exists", prompt.name)),
+            Entry::Vacant(entry) => {
+                entry.insert(Prompt::new(prompt));
+                Ok(())
+            }
+        }
+    }
+
+    fn get(&self, prompt_name: &str) -> Result<Prompt, String> {
+        self.prompts
+            .get(prompt_name)
+            .map(|prompt| prompt.value().clone())
+            .ok_or(format!("No prompt with name {} exists", prompt_name))
+    }
+
+    pub fn respond(
+        &self,
+        prompt_name: &str,
+        identity: &str,
+        text: &str,
+        encrypted: bool,
+    ) -> Result<TextResponse, String> {
+        self.get(prompt_name)?.respond(identity, text, encrypted)
+    }
+
+    pub fn set_moderated(&self, prompt_name: &str, moderated: bool) -> Result<(), String> {
+        self.get(prompt_name)?.set_moderated(moderated);
+        Ok(())
+    }
+
+    pub fn moderate(
+        &self,
+        prompt_name: &str,
+        id: u64,
+        approve: bool,
+    ) -> Result<TextResponse, String> {
+        self.get(prompt_name)?.moderate(id, approve)
+    }
+
+    pub fn snapshot(&self) -> Vec<PromptSnapshot> {
+        self.prompts.iter().map(|x| x.value().snapshot()).collect()
+    }
+
+    pub fn restore(snapshots: Vec<PromptSnapshot>) -> Self {
+        let prompts = Self::new();
+        for snapshot in snapshots {
+            let prompt = Prompt::restore(snapshot);
+            prompts
+                .prompts
+                .insert(prompt.definition.name.clone(), prompt);
+        }
+        prompts
+    }
+
+    pub fn get_approved_responses(&self, prompt_name: &str) -> Option<Vec<ApprovedResponse>> {
+        self.get(prompt_name)
+            .ok()
+            .map(|prompt| prompt.approved_responses())
+    }
+
+    pub fn get_word_cloud(&self, prompt_name: &str) -> Option<HashMap<String, u64>> {
+        self.get(prompt_name).ok().map(|prompt| prompt.word_cloud())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prompt(remove_stop_words: bool, moderated: bool) -> Prompt {
+        Prompt::new(NewPromptMessage {
+            name: "prompt".to_string(),
+            question: "How was it?".to_string(),
+            max_length: Some(40),
+            remove_stop_words,
+            moderated,
+        })
+    }
+
+    #[test]
+    fn words_are_case_folded_and_trimmed() {
+        assert_eq!(
+            normalize("Great talk! (Really) GREAT... don't", false),
+            ["great", "talk", "really", "don't"]
+        );
+        assert_eq!(normalize("Ünïcode — café", false), ["ünïcode", "café"]);
+        assert!(normalize("  ... !!! ", false).is_empty());
+    }
+
+    #[test]
+    fn stop_words_are_removed_when_asked() {
+        assert_eq!(normalize("The talk was THE best", false), ["the", "talk", "was", "best"]);
+        assert_eq!(normalize("The talk was THE best", true), ["talk", "best"]);
+    }
+
+    #[test]
+    fn the_word_cloud_counts_responses_not_words() {
+        let prompt = prompt(true, false);
+        prompt.respond("alice", "Fun fun FUN talk", false).unwrap();
+        prompt.respond("bob", "A fun one", false).unwrap();
+
+        let cloud = prompt.word_cloud();
+        assert_eq!(cloud.get("fun"), Some(&2));
+        assert_eq!(cloud.get("talk"), Some(&1));
+        assert_eq!(cloud.get("a"), None);
+    }
+
+    #[test]
+    fn responses_are_checked() {
+        let prompt = prompt(false, false);
+        assert!(prompt.respond("alice", "   ", false).is_err());
+        assert!(prompt.respond("alice", &"a".repeat(41), false).is_err());
+        assert!(prompt.respond("alice", &"é".repeat(40), false).is_ok());
+        assert!(prompt.respond("alice", "again", false).is_err());
+    }
+
+    #[test]
+    fn moderated_responses_only_count_once_approved() {
+        let prompt = prompt(false, true);
+        let approved = prompt.respond("alice", "good", false).unwrap();
+        let rejected = prompt.respond("bob", "bad", false).unwrap();
+        assert!(prompt.word_cloud().is_empty());
+
+        prompt.moderate(approved.id, true).unwrap();
+        prompt.moderate(rejected.id, false).unwrap();
+        assert!(prompt.moderate(approved.id, false).is_err());
+
+        assert_eq!(prompt.word_cloud(), HashMap::from([("good".to_string(), 1)]));
+        assert_eq!(prompt.approved_responses().len(), 1);
+    }
+}
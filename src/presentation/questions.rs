@@ -0,0 +1,237 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+use super::now_millis;
+use crate::{
+    encryption,
+    processor::{broadcast_to_clients, broadcast_to_presenters},
+    OutgoingPresenterMessage, OutgoingUserMessage, Presenters, Users,
+};
+
+/// The longest question a user can ask in characters
+pub const MAX_QUESTION_LENGTH: usize = 280;
+/// How long changes to the queue are collected for before the audience is
+/// sent it again, in milliseconds
+const AUDIENCE_WINDOW_MS: u64 = 1000;
+/// The same for presenters, who are kept more up to date
+const PRESENTER_WINDOW_MS: u64 = 250;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum QuestionStatus {
+    /// Kept at the top of the queue by the presenter
+    Pinned,
+    /// Waiting to be answered
+    Open,
+    Answered,
+    /// Hidden from everyone by the presenter
+    Dismissed,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Question {
+    id: u64,
+    identity: String,
+    text: String,
+    /// Milliseconds since the epoch
+    asked_at: u64,
+    /// Identities that have upvoted the question
+    upvotes: HashSet<String>,
+    status: QuestionStatus,
+}
+
+/// A question as it is shown in the queue. The identity of whoever
+/// asked is only given to presenters.
+#[derive(Clone, Debug, Serialize)]
+pub struct QueuedQuestion {
+    pub id: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identity: Option<String>,
+    pub text: String,
+    pub upvotes: u64,
+    pub status: QuestionStatus,
+}
+
+/// Everything needed to rebuild the Q&A for a presentation
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct QuestionsSnapshot {
+    questions: Vec<Question>,
+    next_id: u64,
+}
+
+/// The audience Q&A for a presentation
+#[derive(Clone)]
+pub struct Questions {
+    questions: Arc<DashMap<u64, Question>>,
+    next_id: Arc<AtomicU64>,
+    /// Whether an updated queue is waiting to go out to the audience
+    audience_pending: Arc<AtomicBool>,
+    presenter_pending: Arc<AtomicBool>,
+}
+
+impl Default for Questions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Questions {
+    pub fn new() -> Self {
+        Self {
+            questions: Arc::new(DashMap::new()),
+            next_id: Arc::new(AtomicU64::new(0)),
+            audience_pending: Arc::new(AtomicBool::new(false)),
+            presenter_pending: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn snapshot(&self) -> QuestionsSnapshot {
+        QuestionsSnapshot {
+            questions: self.questions.iter().map(|x| x.value().clone()).collect(),
+            next_id: self.next_id.load(Ordering::SeqCst),
+        }
+    }
+
+    pub fn restore(snapshot: QuestionsSnapshot) -> Self {
+        let questions = Self::new();
+        questions.next_id.store(snapshot.next_id, Ordering::SeqCst);
+        for question in snapshot.questions {
+            questions.questions.insert(question.id, question);
+        }
+        questions
+    }
+
+    /// Add a question to the queue and return its id
+    pub fn ask(&self, identity: &str, text: &str, encrypted: bool) -> Result<u64, String> {
+        let text = text.trim();
+        let length = if encrypted {
+            encryption::check_sealed("Questions", text)?
+        } else {
+            text.chars().count()
+        };
+        // Sealed content can be empty too so check what it holds
+        if length == 0 {
+            return Err("Questions cannot be empty".to_string());
+        }
+        if length > MAX_QUESTION_LENGTH {
+            return Err(format!(
+                "Questions can be at most {MAX_QUESTION_LENGTH} {}",
+                if encrypted { "bytes" } else { "characters" }
+            ));
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.questions.insert(
+            id,
+            Question {
+                id,
+                identity: identity.to_string(),
+                text: text.to_string(),
+                asked_at: now_millis(),
+                upvotes: HashSet::new(),
+                status: QuestionStatus::Open,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Upvote a question. Every identity gets one upvote per question
+    /// and cannot upvote their own question.
+    pub fn upvote(&self, identity: &str, id: u64) -> Result<(), String> {
+        let mut question = self
+            .questions
+            .get_mut(&id)
+            .ok_or(format!("No question with id {id} exists"))?;
+
+        if question.status == QuestionStatus::Dismissed {
+            return Err(format!("No question with id {id} exists"));
+        }
+        if question.identity == identity {
+            return Err("You cannot upvote your own question".to_string());
+        }
+        if !question.upvotes.insert(identity.to_string()) {
+            return Err("You have already upvoted this question".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn set_status(&self, id: u64, status: QuestionStatus) -> Result<(), String> {
+        let mut question = self
+            .questions
+            .get_mut(&id)
+            .ok_or(format!("No question with id {id} exists"))?;
+        question.status = status;
+        Ok(())
+    }
+
+    /// Every question that hasn't been dismissed, sorted with pinned questions
+    /// first, then open questions by upvotes and finally answered questions.
+    /// Ties go to whoever asked first.
+    pub fn queue(&self, include_identities: bool) -> Vec<QueuedQuestion> {
+        let mut questions: Vec<Question> = self
+            .questions
+            .iter()
+            .filter(|question| question.status != QuestionStatus::Dismissed)
+            .map(|question| question.value().clone())
+            .collect();
+
+        questions.sort_by(|a, b| {
+            a.status
+                .cmp(&b.status)
+                .then_with(|| b.upvotes.len().cmp(&a.upvotes.len()))
+                .then_with(|| a.asked_at.cmp(&b.asked_at))
+                .then_with(|| a.id.cmp(&b.id))
+        });
+
+        questions
+            .into_iter()
+            .map(|question| QueuedQuestion {
+                id: question.id,
+                identity: include_identities.then_some(question.identity),
+                text: question.text,
+                upvotes: question.upvotes.len() as u64,
+                status: question.status,
+            })
+            .collect()
+    }
+
+    /// Send the queue to presenters and the audience once their current
+    /// windows end. Every question and upvote in a window goes out in that
+    /// one update, so a busy Q&A doesn't send the whole queue to everyone for
+    /// each change. Presenters see who asked each question.
+    pub fn changed(&self, presenters: Presenters, users: Users) {
+        // An update that is already waiting to go out will include this change
+        if !self.presenter_pending.swap(true, Ordering::SeqCst) {
+            let questions = self.clone();
+            tokio::task::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(PRESENTER_WINDOW_MS)).await;
+                // Clear pending before reading the queue so a change that lands
+                // while we are sending schedules another update
+                questions.presenter_pending.store(false, Ordering::SeqCst);
+                broadcast_to_presenters(
+                    OutgoingPresenterMessage::QuestionQueue(questions.queue(true)),
+                    presenters,
+                )
+                .await;
+            });
+        }
+
+        if !self.audience_pending.swap(true, Ordering::SeqCst) {
+            let questions = self.clone();
+            tokio::task::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(AUDIENCE_WINDOW_MS)).await;
+                questions.audience_pending.store(false, Ordering::SeqCst);
+                broadcast_to_clients(OutgoingUserMessage::Questions(questions.queue(false)), users)
+                    .await;
+            });
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+fn default_points() -> u64 {
+    100
+}
+
+/// Turns a poll into a quiz question. This is never sent to users
+/// so the answer stays secret until the presenter reveals it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct QuizSettings {
+    /// The choices that make up the correct answer. For polls that allow
+    /// picking multiple choices a user must pick exactly these to be correct.
+    pub correct: Vec<String>,
+    /// How many points a correct answer is worth
+    #[serde(default = "default_points")]
+    pub points: u64,
+    /// If set, faster correct answers earn more points. The slowest correct
+    /// answer earns half of the points.
+    #[serde(default)]
+    pub speed_bonus: bool,
+}
+
+impl QuizSettings {
+    /// Make sure every correct choice is one of the poll's options
+    pub fn validate(&self, options: &[String]) -> Result<(), String> {
+        if self.correct.is_empty() {
+            return Err("A quiz needs at least one correct choice".to_string());
+        }
+        match self.correct.iter().find(|choice| !options.contains(choice)) {
+            Some(choice) => Err(format!("Correct choice [{choice}] is not an option")),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_correct(&self, picked: &HashSet<&String>) -> bool {
+        picked.len() == self.correct.len() && self.correct.iter().all(|x| picked.contains(x))
+    }
+
+    /// Points for a correct answer given `elapsed` milliseconds out of a
+    /// `window` the user had to answer in
+    pub fn score(&self, elapsed: u64, window: u64) -> u64 {
+        if !self.speed_bonus || window == 0 {
+            return self.points;
+        }
+
+        let elapsed = elapsed.min(window);
+        let half = self.points / 2;
+        // Both come from the presenter so the product can be bigger than a u64
+        let bonus = u128::from(self.points - half) * u128::from(window - elapsed)
+            / u128::from(window);
+        half + bonus as u64
+    }
+}
+
+/// The result of a single identity's answer to a revealed quiz question
+#[derive(Clone, Debug, Serialize)]
+pub struct QuizAnswer {
+    pub correct: bool,
+    pub points: u64,
+}
+
+/// Everything that is needed to tell users and presenters how
+/// a quiz question went
+#[derive(Clone, Debug)]
+pub struct QuizReveal {
+    pub correct: Vec<String>,
+    pub answers: HashMap<String, QuizAnswer>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LeaderboardEntry {
+    pub rank: u64,
+    pub identity: String,
+    pub points: u64,
+    pub correct_answers: u64,
+}
+
+/// Rank everyone who has answered a revealed quiz question. Identities with
+/// the same number of points share a rank.
+pub fn leaderboard(reveals: impl Iterator<Item = QuizReveal>) -> Vec<LeaderboardEntry> {
+    let mut scores: HashMap<String, (u64, u64)> = HashMap::new();
+    for reveal in reveals {
+        for (identity, answer) in reveal.answers {
+            let score = scores.entry(identity).or_default();
+            score.0 += answer.points;
+            score.1 += answer.correct as u64;
+        }
+    }
+
+    let mut scores: Vec<(String, (u64, u64))> = scores.into_iter().collect();
+    scores.sort_by(|a, b| b.1 .0.cmp(&a.1 .0).then_with(|| a.0.cmp(&b.0)));
+
+    let mut entries: Vec<LeaderboardEntry> = Vec::with_capacity(scores.len());
+    for (position, (identity, (points, correct_answers))) in scores.into_iter().enumerate() {
+        let rank = match entries.last() {
+            Some(previous) if previous.points == points => previous.rank,
+            _ => position as u64 + 1,
+        };
+        entries.push(LeaderboardEntry {
+            rank,
+            identity,
+            points,
+            correct_answers,
+        });
+    }
+
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quiz(correct: &[&str], points: u64, speed_bonus: bool) -> QuizSettings {
+        QuizSettings {
+            correct: correct.iter().map(|x| x.to_string()).collect(),
+            points,
+            speed_bonus,
+        }
+    }
+
+    fn reveal(answers: &[(&str, bool, u64)]) -> QuizReveal {
+        QuizReveal {
+            correct: vec!["a".to_string()],
+            answers: answers
+                .iter()
+                .map(|(identity, correct, points)| {
+                    let answer = QuizAnswer {
+                        correct: *correct,
+                        points: *points,
+                    };
+                    (identity.to_string(), answer)
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn correct_choices_must_be_options() {
+        let options = vec!["a".to_string(), "b".to_string()];
+        assert!(quiz(&["a"], 100, false).validate(&options).is_ok());
+        assert!(quiz(&["c"], 100, false).validate(&options).is_err());
+        assert!(quiz(&[], 100, false).validate(&options).is_err());
+    }
+
+    #[test]
+    fn answers_must_pick_exactly_the_correct_choices() {
+        let quiz = quiz(&["a", "b"], 100, false);
+        let (a, b, c) = ("a".to_string(), "b".to_string(), "c".to_string());
+        assert!(quiz.is_correct(&HashSet::from([&a, &b])));
+        assert!(!quiz.is_correct(&HashSet::from([&a])));
+        assert!(!quiz.is_correct(&HashSet::from([&a, &b, &c])));
+    }
+
+    #[test]
+    fn scores_without_a_speed_bonus_are_fixed() {
+        let quiz = quiz(&["a"], 100, false);
+        assert_eq!(quiz.score(0, 10_000), 100);
+        assert_eq!(quiz.score(10_000, 10_000), 100);
+    }
+
+    #[test]
+    fn faster_answers_score_more() {
+        let quiz = quiz(&["a"], 100, true);
+        assert_eq!(quiz.score(0, 10_000), 100);
+        assert_eq!(quiz.score(5_000, 10_000), 75);
+        assert_eq!(quiz.score(10_000, 10_000), 50);
+        // Answers after the window still get half
+        assert_eq!(quiz.score(20_000, 10_000), 50);
+        // Without a window there is nothing to be fast against
+        assert_eq!(quiz.score(5_000, 0), 100);
+    }
+
+    #[test]
+    fn huge_scores_do_not_overflow() {
+        let quiz = quiz(&["a"], u64::MAX, true);
+        assert_eq!(quiz.score(0, u64::MAX), u64::MAX);
+        assert_eq!(quiz.score(u64::MAX, u64::MAX), u64::MAX / 2);
+    }
+
+    #[test]
+    fn the_leaderboard_adds_up_every_question() {
+        let reveals = vec![
+            reveal(&[("alice", true, 100), ("bob", false, 0), ("carol", true, 60)]),
+            reveal(&[("alice", false, 0), ("bob", true, 100), ("dave", true, 90)]),
+        ];
+        let entries = leaderboard(reveals.into_iter());
+
+        let ranked: Vec<(u64, &str, u64, u64)> = entries
+            .iter()
+            .map(|x| (x.rank, x.identity.as_str(), x.points, x.correct_answers))
+            .collect();
+        assert_eq!(
+            ranked,
+            [
+                (1, "alice", 100, 1),
+                (1, "bob", 100, 1),
+                (3, "dave", 90, 1),
+                (4, "carol", 60, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_leaderboard_starts_empty() {
+        assert!(leaderboard(std::iter::empty()).is_empty());
+    }
+}
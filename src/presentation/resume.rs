@@ -0,0 +1,125 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use uuid::Uuid;
+
+use super::now_millis;
+
+/// How long a token keeps working after its connection drops, in milliseconds
+const RESUME_WINDOW_MS: u64 = 120_000;
+
+struct ResumeToken {
+    identity: String,
+    /// When the token stops working, in milliseconds since the epoch. Not set
+    /// while the connection it was issued for is still open.
+    expires_at: Option<u64>,
+}
+
+impl ResumeToken {
+    fn is_valid(&self, now: u64) -> bool {
+        self.expires_at.map(|x| now < x).unwrap_or(true)
+    }
+}
+
+/// Single use tokens that let a user whose connection dropped come back as
+/// the same identity without their JWT. Each connection gets a new one.
+#[derive(Clone, Default)]
+pub struct ResumeTokens {
+    tokens: Arc<DashMap<String, ResumeToken>>,
+}
+
+impl ResumeTokens {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a token for a connection that just opened. Any earlier token for
+    /// the same identity stops working.
+    pub fn issue(&self, identity: &str) -> String {
+        let now = now_millis();
+        self.tokens
+            .retain(|_, token| token.identity != identity && token.is_valid(now));
+
+        let token = Uuid::new_v4().as_simple().to_string();
+        self.tokens.insert(
+            token.clone(),
+            ResumeToken {
+                identity: identity.to_string(),
+                expires_at: None,
+            },
+        );
+        token
+    }
+
+    /// Start the clock on a token once its connection has closed
+    pub fn connection_closed(&self, token: &str) {
+        if let Some(mut token) = self.tokens.get_mut(token) {
+            token.expires_at = Some(now_millis() + RESUME_WINDOW_MS);
+        }
+    }
+
+    /// Use up a token, returning the identity it was issued to if it is still
+    /// valid. Tokens only work once their connection has closed, otherwise
+    /// whoever holds one could take over a live session, and those are left
+    /// alone so a connection that is about to drop can still be resumed.
+    pub fn redeem(&self, token: &str) -> Option<String> {
+        let (_, token) = self
+            .tokens
+            .remove_if(token, |_, token| token.expires_at.is_some())?;
+        token.is_valid(now_millis()).then_some(token.identity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn live_connections_can_not_be_taken_over() {
+        let tokens = ResumeTokens::new();
+        let token = tokens.issue("someone");
+
+        assert_eq!(tokens.redeem(&token), None);
+
+        // The failed attempt doesn't stop it working once the connection drops
+        tokens.connection_closed(&token);
+        assert_eq!(tokens.redeem(&token).as_deref(), Some("someone"));
+    }
+
+    #[test]
+    fn tokens_only_work_once() {
+        let tokens = ResumeTokens::new();
+        let token = tokens.issue("someone");
+        tokens.connection_closed(&token);
+
+        assert_eq!(tokens.redeem(&token).as_deref(), Some("someone"));
+        assert_eq!(tokens.redeem(&token), None);
+        assert_eq!(tokens.redeem("unknown"), None);
+    }
+
+    #[test]
+    fn tokens_expire() {
+        let tokens = ResumeTokens::new();
+        let token = tokens.issue("someone");
+        tokens.connection_closed(&token);
+        tokens.tokens.get_mut(&token).unwrap().expires_at = Some(now_millis() - 1);
+
+        assert_eq!(tokens.redeem(&token), None);
+    }
+
+    #[test]
+    fn new_connections_replace_earlier_tokens() {
+        let tokens = ResumeTokens::new();
+        let first = tokens.issue("someone");
+        let other = tokens.issue("someone else");
+        tokens.connection_closed(&first);
+        tokens.connection_closed(&other);
+
+        let second = tokens.issue("someone");
+        tokens.connection_closed(&second);
+
+        assert_eq!(tokens.redeem(&first), None);
+        assert_eq!(tokens.redeem(&other).as_deref(), Some("someone else"));
+        assert_eq!(tokens.redeem(&second).as_deref(), Some("someone"));
+    }
+}
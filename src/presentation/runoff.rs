@@ -0,0 +1,206 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+/// The first preference counts for one round of an instant-runoff tally
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RunoffRound {
+    /// Votes for every candidate still in the running
+    pub counts: HashMap<String, u64>,
+    /// Ballots that have no remaining candidates ranked
+    pub exhausted: u64,
+    /// Candidates eliminated at the end of this round
+    pub eliminated: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RunoffResults {
+    pub rounds: Vec<RunoffRound>,
+    pub winner: Option<String>,
+    /// If the tally ended with every remaining candidate tied, who they were
+    pub tied: Vec<String>,
+}
+
+/// Run an instant-runoff tally. Each round every ballot counts for its highest
+/// ranked candidate still in the running. A candidate with a majority of the
+/// ballots that are not exhausted wins, otherwise the candidates with the fewest
+/// votes are eliminated together and the next round is run.
+pub fn instant_runoff(candidates: &HashSet<String>, ballots: &[Vec<String>]) -> RunoffResults {
+    let mut remaining: HashSet<&String> = candidates.iter().collect();
+    let mut rounds = Vec::new();
+
+    loop {
+        let mut counts: HashMap<String, u64> =
+            remaining.iter().map(|x| (x.to_string(), 0)).collect();
+        let mut exhausted = 0;
+        for ballot in ballots {
+            match ballot.iter().find(|choice| remaining.contains(choice)) {
+                Some(choice) => *counts.entry(choice.clone()).or_default() += 1,
+                None => exhausted += 1,
+            }
+        }
+
+        let active: u64 = counts.values().sum();
+        let leader = counts.iter().max_by_key(|(_, votes)| **votes);
+        if let Some((leader, votes)) = leader {
+            if active > 0 && (votes * 2 > active || remaining.len() == 1) {
+                let winner = leader.clone();
+                rounds.push(RunoffRound {
+                    counts,
+                    exhausted,
+                    eliminated: vec![],
+                });
+                return RunoffResults {
+                    rounds,
+                    winner: Some(winner),
+                    tied: vec![],
+                };
+            }
+        }
+
+        let fewest = counts.values().min().copied().unwrap_or(0);
+        let mut eliminated: Vec<String> = counts
+            .iter()
+            .filter(|(_, votes)| **votes == fewest)
+            .map(|(candidate, _)| candidate.clone())
+            .collect();
+        eliminated.sort();
+
+        // Eliminating everyone left means they are all tied
+        if active == 0 || eliminated.len() == remaining.len() {
+            rounds.push(RunoffRound {
+                counts,
+                exhausted,
+                eliminated: vec![],
+            });
+            return RunoffResults {
+                rounds,
+                winner: None,
+                tied: if active == 0 { vec![] } else { eliminated },
+            };
+        }
+
+        remaining.retain(|candidate| !eliminated.contains(candidate));
+        rounds.push(RunoffRound {
+            counts,
+            exhausted,
+            eliminated,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|x| x.to_string()).collect()
+    }
+
+    fn ballot(ranking: &[&str]) -> Vec<String> {
+        ranking.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn majority_wins_in_the_first_round() {
+        let ballots = [ballot(&["a", "b"]), ballot(&["a"]), ballot(&["b", "a"])];
+        let results = instant_runoff(&candidates(&["a", "b"]), &ballots);
+
+        assert_eq!(results.winner.as_deref(), Some("a"));
+        assert_eq!(results.rounds.len(), 1);
+        assert_eq!(results.rounds[0].counts["a"], 2);
+        assert_eq!(results.rounds[0].counts["b"], 1);
+        assert!(results.rounds[0].eliminated.is_empty());
+        assert!(results.tied.is_empty());
+    }
+
+    #[test]
+    fn eliminated_votes_move_to_the_next_preference() {
+        let ballots = [
+            ballot(&["a"]),
+            ballot(&["a"]),
+            ballot(&["b"]),
+            ballot(&["b", "a"]),
+            ballot(&["c", "b"]),
+        ];
+        let results = instant_runoff(&candidates(&["a", "b", "c"]), &ballots);
+
+        assert_eq!(results.rounds.len(), 2);
+        assert_eq!(results.rounds[0].eliminated, vec!["c".to_string()]);
+        assert_eq!(results.rounds[1].counts["b"], 3);
+        assert!(!results.rounds[1].counts.contains_key("c"));
+        assert_eq!(results.winner.as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn candidates_tied_for_last_are_eliminated_together() {
+        let ballots = [
+            ballot(&["a"]),
+            ballot(&["a"]),
+            ballot(&["a"]),
+            ballot(&["b"]),
+            ballot(&["b"]),
+            ballot(&["c"]),
+            ballot(&["d"]),
+        ];
+        let results = instant_runoff(&candidates(&["a", "b", "c", "d"]), &ballots);
+
+        assert_eq!(results.rounds[0].eliminated, vec!["c".to_string(), "d".to_string()]);
+        assert_eq!(results.rounds.len(), 2);
+        assert_eq!(results.winner.as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn exhausted_ballots_do_not_count_towards_a_majority() {
+        // a has 3 of the 6 ballots, but 3 of the 5 still in play once c is out
+        let ballots = [
+            ballot(&["a", "b"]),
+            ballot(&["a"]),
+            ballot(&["a"]),
+            ballot(&["b"]),
+            ballot(&["b"]),
+            ballot(&["c"]),
+        ];
+        let results = instant_runoff(&candidates(&["a", "b", "c"]), &ballots);
+
+        assert_eq!(results.rounds.len(), 2);
+        assert_eq!(results.rounds[0].exhausted, 0);
+        assert_eq!(results.rounds[1].exhausted, 1);
+        assert_eq!(results.rounds[1].counts["a"], 3);
+        assert_eq!(results.winner.as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn a_tie_between_everyone_left_has_no_winner() {
+        let ballots = [
+            ballot(&["a"]),
+            ballot(&["a"]),
+            ballot(&["b"]),
+            ballot(&["b"]),
+            ballot(&["c"]),
+        ];
+        let results = instant_runoff(&candidates(&["a", "b", "c"]), &ballots);
+
+        assert_eq!(results.rounds.len(), 2);
+        assert_eq!(results.rounds[1].exhausted, 1);
+        assert!(results.rounds[1].eliminated.is_empty());
+        assert_eq!(results.winner, None);
+        assert_eq!(results.tied, vec!["a".to_string(), "b".to_string()]);
+    }
+
+    #[test]
+    fn no_ballots_has_no_winner_and_no_tie() {
+        let results = instant_runoff(&candidates(&["a", "b"]), &[]);
+
+        assert_eq!(results.rounds.len(), 1);
+        assert_eq!(results.winner, None);
+        assert!(results.tied.is_empty());
+    }
+
+    #[test]
+    fn the_last_candidate_standing_wins() {
+        let results = instant_runoff(&candidates(&["a"]), &[ballot(&["a"])]);
+
+        assert_eq!(results.winner.as_deref(), Some("a"));
+    }
+}
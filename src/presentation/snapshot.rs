@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+
+use jsonwebtoken::Algorithm;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    emoji_batch::{EmojiBatchSettings, EmojiBatcher}, engagement::EngagementSnapshot, poll::PollSnapshot, prompt::PromptSnapshot, questions::QuestionsSnapshot, Polls,
+    Engagement, Presentation, PresentationData, Prompts, PollSubscriptions, Questions, ResumeTokens,
+    Updates,
+};
+use crate::{
+    ratelimiting::LimiterType,
+    SlideSettings,
+};
+
+/// Everything needed to bring a presentation back after a restart. Connected
+/// clients are not included, they rejoin with their existing JWTs.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PresentationSnapshot {
+    pub id: String,
+    /// When the snapshot was started, in milliseconds since the epoch. Journal
+    /// events from after this are replayed on top of it after a restart.
+    pub taken_at: u64,
+    pub presenter_identity: String,
+    pub encrypted: bool,
+    pub authentication_key_source: String,
+    pub authentication_algorithms: Vec<Algorithm>,
+    pub title: String,
+    pub slide_settings: Option<SlideSettings>,
+    /// Only limiters added from a configuration can be saved
+    pub ratelimiters: HashMap<String, LimiterType>,
+    polls: Vec<PollSnapshot>,
+    prompts: Vec<PromptSnapshot>,
+    questions: QuestionsSnapshot,
+    engagement: EngagementSnapshot,
+    emoji_batching: EmojiBatchSettings,
+}
+
+impl Presentation {
+    pub async fn snapshot(&self) -> PresentationSnapshot {
+        PresentationSnapshot {
+            // Taken first so events handled while the snapshot is being built
+            // are replayed again rather than lost
+            taken_at: super::now_millis(),
+            id: self.id.clone(),
+            presenter_identity: self.presenter_identity.clone(),
+            encrypted: self.encrypted,
+            authentication_key_source: self.authentication_key_source.clone(),
+            authentication_algorithms: self.authentication_algorithms.clone(),
+            title: self.get_title(),
+            slide_settings: self.slide_settings.read().await.clone(),
+            ratelimiters: self.ratelimiter.configurations(),
+            polls: self.presentation_data.polls.snapshot(),
+            prompts: self.presentation_data.prompts.snapshot(),
+            questions: self.presentation_data.questions.snapshot(),
+            engagement: self.presentation_data.engagement.snapshot(),
+            emoji_batching: self.presentation_data.emoji_batcher.settings(),
+        }
+    }
+
+    /// Rebuild a presentation from a snapshot. This fails if the saved
+    /// authentication key can no longer be parsed.
+    pub fn restore(snapshot: PresentationSnapshot) -> Result<Self, String> {
+        let presentation_data = PresentationData {
+            title: snapshot.title,
+            polls: Polls::restore(snapshot.polls),
+            poll_subscriptions: PollSubscriptions::new(),
+            prompts: Prompts::restore(snapshot.prompts),
+            questions: Questions::restore(snapshot.questions),
+            engagement: Engagement::restore(snapshot.engagement),
+            emoji_batcher: EmojiBatcher::restore(snapshot.emoji_batching),
+            // Sockets don't survive a restart so neither does anything used to resume them
+            updates: Updates::new(),
+            resume_tokens: ResumeTokens::new(),
+        };
+
+        let presentation = Self::from_parts(
+            snapshot.id,
+            snapshot.presenter_identity,
+            snapshot.encrypted,
+            snapshot.authentication_key_source,
+            snapshot.authentication_algorithms,
+            presentation_data,
+            snapshot.slide_settings,
+        )?;
+
+        for (name, limiter) in snapshot.ratelimiters {
+            presentation.ratelimiter.add_configured_ratelimit(name, limiter);
+        }
+
+        // Timed polls need to be handed back to the timer. Any that ran out
+        // while we were down are closed as soon as the timer sees them.
+        for poll_name in presentation.get_polls().timed_polls() {
+            presentation.time_poll(poll_name);
+        }
+
+        Ok(presentation)
+    }
+}
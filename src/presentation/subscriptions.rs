@@ -0,0 +1,130 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use dashmap::{DashMap, DashSet};
+
+use super::{ChoiceTotals, Polls, RunoffResults};
+use crate::{OutgoingPresenterMessage, Presenters};
+
+/// How long to collect votes for before sending updated totals if
+/// the presenter does not ask for something different
+const DEFAULT_WINDOW_MS: u64 = 250;
+/// The shortest and longest window a presenter can ask for, in milliseconds
+const MIN_WINDOW_MS: u64 = 50;
+const MAX_WINDOW_MS: u64 = 10_000;
+
+/// Tracks which presenters want live results for which polls. Updates are
+/// coalesced so that a burst of votes only sends one set of totals per window.
+/// Every subscription has its own window so one presenter can't change how
+/// often another gets updates.
+#[derive(Clone)]
+pub struct PollSubscriptions {
+    /// Maps the name of a poll to the guids of the presenters subscribed to
+    /// it and the window, in milliseconds, each of them asked for
+    subscribers: Arc<DashMap<String, HashMap<String, u64>>>,
+    /// Subscriptions, as poll name and presenter guid, that have had votes
+    /// since their last totals were sent
+    pending: Arc<DashSet<(String, String)>>,
+}
+
+impl Default for PollSubscriptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PollSubscriptions {
+    pub fn new() -> Self {
+        Self {
+            subscribers: Arc::new(DashMap::new()),
+            pending: Arc::new(DashSet::new()),
+        }
+    }
+
+    /// Subscribe a presenter to live results for a poll, collecting votes for
+    /// `window` milliseconds before each update. Subscribing again changes
+    /// the window.
+    pub fn subscribe(&self, poll_name: &str, presenter_guid: &str, window: Option<u64>) {
+        let window = window
+            .unwrap_or(DEFAULT_WINDOW_MS)
+            .clamp(MIN_WINDOW_MS, MAX_WINDOW_MS);
+        self.subscribers
+            .entry(poll_name.to_string())
+            .or_default()
+            .insert(presenter_guid.to_string(), window);
+    }
+
+    pub fn unsubscribe(&self, poll_name: &str, presenter_guid: &str) {
+        if let Some(mut subscribers) = self.subscribers.get_mut(poll_name) {
+            subscribers.remove(presenter_guid);
+        }
+        self.subscribers
+            .remove_if(poll_name, |_, subscribers| subscribers.is_empty());
+    }
+
+    /// Drop every subscription to a poll, used when the poll is deleted
+    pub fn remove_poll(&self, poll_name: &str) {
+        self.subscribers.remove(poll_name);
+    }
+
+    /// Called whenever the totals of a poll change. Each subscriber is sent the
+    /// new totals once their window has passed. Any further changes inside that
+    /// window are included in the same update.
+    pub fn poll_changed(&self, poll_name: &str, polls: Polls, presenters: Presenters) {
+        let subscribers = match self.subscribers.get(poll_name) {
+            Some(subscribers) => subscribers.value().clone(),
+            None => return,
+        };
+
+        for (guid, window) in subscribers {
+            // An update is already waiting to go out and will include this change
+            let key = (poll_name.to_string(), guid);
+            if !self.pending.insert(key.clone()) {
+                continue;
+            }
+
+            let subscriptions = self.clone();
+            let polls = polls.clone();
+            let presenters = presenters.clone();
+            tokio::task::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(window)).await;
+                // Clear pending before reading the totals so a vote that lands
+                // while we are sending schedules another update
+                subscriptions.pending.remove(&key);
+
+                let (poll_name, guid) = key;
+                let totals = match polls.get_poll_totals(&poll_name) {
+                    Some(totals) => totals,
+                    None => return,
+                };
+                let runoff = polls.get_poll_runoff(&poll_name);
+                subscriptions.send_totals(&poll_name, &guid, totals, runoff, &presenters);
+            });
+        }
+    }
+
+    fn send_totals(
+        &self,
+        poll_name: &str,
+        presenter_guid: &str,
+        totals: HashMap<String, ChoiceTotals>,
+        runoff: Option<RunoffResults>,
+        presenters: &Presenters,
+    ) {
+        // They may have unsubscribed while the window was open
+        let subscribed = self
+            .subscribers
+            .get(poll_name)
+            .is_some_and(|subscribers| subscribers.contains_key(presenter_guid));
+        if !subscribed {
+            return;
+        }
+
+        if let Some(presenter) = presenters.get(presenter_guid) {
+            presenter.send_ignore_fail(OutgoingPresenterMessage::PollResults {
+                name: poll_name.to_string(),
+                totals,
+                runoff,
+            });
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use std::{cmp::Reverse, collections::BinaryHeap, time::Duration};
+
+use tokio::sync::mpsc;
+
+use super::{now_millis, Polls, Updates};
+use crate::{
+    processor::{broadcast_to_presenters, broadcast_update},
+    OutgoingPresenterMessage, OutgoingUserMessage, Presenters, Users,
+};
+
+/// Start the task that closes timed polls for a presentation. The returned
+/// sender is used to tell the task about newly created timed polls.
+///
+/// The task only holds the pieces of the presentation it needs and not the
+/// sender, so once every copy of the presentation has been dropped the channel
+/// closes and the task exits with it.
+pub fn spawn_poll_timer(
+    polls: Polls,
+    updates: Updates,
+    users: Users,
+    presenters: Presenters,
+) -> mpsc::UnboundedSender<String> {
+    let (sender, mut receiver) = mpsc::unbounded_channel::<String>();
+
+    tokio::task::spawn(async move {
+        // Deadlines in milliseconds since the epoch along with the poll they close
+        let mut deadlines: BinaryHeap<Reverse<(u64, String)>> = BinaryHeap::new();
+
+        loop {
+            let next_deadline = deadlines.peek().map(|Reverse((deadline, _))| *deadline);
+            let wait = async {
+                match next_deadline {
+                    Some(deadline) => {
+                        let remaining = deadline.saturating_sub(now_millis());
+                        tokio::time::sleep(Duration::from_millis(remaining)).await
+                    }
+                    None => std::future::pending().await,
+                }
+            };
+
+            tokio::select! {
+                poll_name = receiver.recv() => {
+                    let poll_name = match poll_name {
+                        Some(poll_name) => poll_name,
+                        None => break,
+                    };
+
+                    match polls.get_poll(&poll_name).and_then(|poll| poll.deadline()) {
+                        Some(deadline) => deadlines.push(Reverse((deadline, poll_name))),
+                        None => warn!("Asked to time poll [{poll_name}] but it has no deadline"),
+                    }
+                }
+                _ = wait => {
+                    let (deadline, poll_name) = match deadlines.pop() {
+                        Some(Reverse(next)) => next,
+                        None => continue,
+                    };
+
+                    let poll = match polls.expire_poll(&poll_name, deadline) {
+                        Some(poll) => poll,
+                        None => {
+                            debug!("Timed poll [{poll_name}] was changed before its deadline");
+                            continue;
+                        }
+                    };
+
+                    info!("Time is up for poll [{poll_name}], closing it");
+                    let totals = poll.totals();
+                    let runoff = poll.runoff();
+                    broadcast_to_presenters(
+                        OutgoingPresenterMessage::PollResults {
+                            name: poll_name.clone(),
+                            totals: totals.clone(),
+                            runoff: runoff.clone(),
+                        },
+                        presenters.clone(),
+                    )
+                    .await;
+                    broadcast_update(OutgoingUserMessage::PollClosed(poll_name.clone()), &updates, &users);
+
+                    if poll.definition().share_results {
+                        broadcast_update(
+                            OutgoingUserMessage::PollResults {
+                                name: poll_name,
+                                totals,
+                                runoff,
+                            },
+                            &updates,
+                            &users,
+                        );
+                    }
+                }
+            }
+        }
+
+        debug!("Poll timer is shutting down");
+    });
+
+    sender
+}
@@ -0,0 +1,128 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use crate::{OutgoingUserMessage, SequencedUserMessage};
+
+/// How many updates are kept for clients that resume after a dropped connection
+const HISTORY_LENGTH: usize = 256;
+
+#[derive(Default)]
+struct History {
+    last_seq: u64,
+    /// Recent updates as they were sent, oldest first
+    sent: VecDeque<(u64, String)>,
+}
+
+/// Numbers the changes to slides, polls and prompts that are sent to every
+/// user so clients can spot one they missed, and remembers the last few so
+/// they can be sent again when a client resumes.
+///
+/// Publishing and catching up hold the same lock, so a client that is catching
+/// up can't see a newer update before the ones it missed.
+#[derive(Clone, Default)]
+pub struct Updates {
+    history: Arc<Mutex<History>>,
+}
+
+impl Updates {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Give an update the next sequence number and pass the JSON to `send`
+    pub fn publish(&self, message: &OutgoingUserMessage, send: impl FnOnce(&str)) {
+        let mut history = self.history.lock().unwrap();
+        let seq = history.last_seq + 1;
+        let event = SequencedUserMessage { seq, message }.json();
+
+        history.last_seq = seq;
+        history.sent.push_back((seq, event));
+        if history.sent.len() > HISTORY_LENGTH {
+            history.sent.pop_front();
+        }
+
+        send(&history.sent.back().unwrap().1);
+    }
+
+    /// Call `send` with the latest sequence number and every update after
+    /// `since` that is still remembered. If updates were forgotten the client
+    /// will see a gap in the sequence numbers.
+    pub fn catch_up(&self, since: Option<u64>, send: impl FnOnce(u64, Vec<&str>)) {
+        let history = self.history.lock().unwrap();
+        let missed = match since {
+            Some(since) => history
+                .sent
+                .iter()
+                .filter(|(seq, _)| *seq > since)
+                .map(|(_, event)| event.as_str())
+                .collect(),
+            None => vec![],
+        };
+        send(history.last_seq, missed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Publish `count` updates, returning what was sent for each
+    fn publish(updates: &Updates, count: usize) -> Vec<String> {
+        (0..count)
+            .map(|i| {
+                let mut sent = String::new();
+                updates.publish(&OutgoingUserMessage::PollClosed(i.to_string()), |event| {
+                    sent = event.to_string()
+                });
+                sent
+            })
+            .collect()
+    }
+
+    fn catch_up(updates: &Updates, since: Option<u64>) -> (u64, Vec<String>) {
+        let mut caught_up = (0, Vec::new());
+        updates.catch_up(since, |seq, missed| {
+            caught_up = (seq, missed.into_iter().map(str::to_string).collect())
+        });
+        caught_up
+    }
+
+    fn seq(event: &str) -> u64 {
+        serde_json::from_str::<serde_json::Value>(event).unwrap()["seq"]
+            .as_u64()
+            .unwrap()
+    }
+
+    #[test]
+    fn updates_are_numbered_in_order() {
+        let updates = Updates::new();
+        let sent = publish(&updates, 3);
+
+        assert_eq!(sent.iter().map(|x| seq(x)).collect::<Vec<_>>(), [1, 2, 3]);
+        assert_eq!(catch_up(&updates, None), (3, vec![]));
+    }
+
+    #[test]
+    fn missed_updates_are_sent_again() {
+        let updates = Updates::new();
+        let sent = publish(&updates, 5);
+
+        assert_eq!(catch_up(&updates, Some(2)), (5, sent[2..].to_vec()));
+        assert_eq!(catch_up(&updates, Some(5)), (5, vec![]));
+        assert_eq!(catch_up(&updates, Some(0)), (5, sent));
+    }
+
+    #[test]
+    fn only_recent_updates_are_remembered() {
+        let updates = Updates::new();
+        let sent = publish(&updates, HISTORY_LENGTH + 10);
+
+        let (last, missed) = catch_up(&updates, Some(0));
+        assert_eq!(last, HISTORY_LENGTH as u64 + 10);
+        assert_eq!(missed, sent[10..]);
+        // The client can tell it missed some
+        assert_eq!(seq(&missed[0]), 11);
+    }
+}
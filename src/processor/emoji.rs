@@ -5,12 +5,14 @@ use crate::{
 /// Called from the processor system. Only one processor should be called per user message
 /// which is in a separate tokio task. Again this means we do not need to start tokio tasks
 /// to unblock processesing of further user messages.
+///
+/// Returns whether the emoji was accepted.
 pub async fn handle_user_emoji(
     presentation: &Presentation,
     user: User,
     emoji_message: EmojiMessage,
     presenters: Presenters,
-) {
+) -> bool {
     let slide_settings = presentation.slide_settings.read().await;
     // Check if the presentation has started
     let slide_settings = if let Some(ref s) = *slide_settings {
@@ -20,7 +22,7 @@ pub async fn handle_user_emoji(
             "{} sent a message but the presentation has not started",
             user.identity
         );
-        return;
+        return false;
     };
 
     let emoji = &emoji_message.emoji;
@@ -28,7 +30,7 @@ pub async fn handle_user_emoji(
     // Check that they are sending a valid emoji for the current slide
     if !slide_settings.emojis.contains(emoji) {
         error!("{identity} sent invalid {emoji} for current slide");
-        return;
+        return false;
     }
 
     // Send the emojis to the presenters
@@ -37,5 +39,15 @@ pub async fn handle_user_emoji(
         user.presentation
     );
 
-    super::broadcast_to_presenters(OutgoingPresenterMessage::Emoji(emoji_message), presenters).await;
+    presentation
+        .get_engagement()
+        .emoji_sent(emoji, presenters.clone());
+
+    let batcher = presentation.get_emoji_batcher();
+    if batcher.is_aggregating() {
+        batcher.add(&emoji_message, identity, presenters);
+    } else {
+        super::broadcast_to_presenters(OutgoingPresenterMessage::Emoji(emoji_message), presenters).await;
+    }
+    true
 }
@@ -1,33 +1,43 @@
-use std::sync::Arc;
-
 use warp::ws::Message;
 
 mod emoji;
+mod prompt;
+mod question;
 mod vote;
 
 use crate::{
-    ratelimiting::{Limiter, RatelimiterResponse},
-    IncomingPresenterMessage, IncomingUserMessage, OutgoingPresenterMessage, OutgoingUserMessage,
+    encryption,
+    heartbeat::ConnectionQuality,
+    journal::{self, JournalEntry},
+    presentation::{EmojiBatchSettings, ResponseState, Updates},
+    ratelimiting::RatelimiterResponse,
+    Delivery, IncomingPresenterMessage, IncomingUserMessage, OutgoingMessage, OutgoingPresenterMessage, OutgoingUserMessage,
     Presentation, Presenter, Presenters, User, Users,
 };
 
 pub async fn broadcast_to_presenters(message: OutgoingPresenterMessage, presenters: Presenters) {
     let event = serde_json::to_string(&message).unwrap();
+    let delivery = message.delivery();
     presenters.iter().for_each(|item| {
-        let connected_presenter = item.value();
-        if let Some(ref connected_presenter) = connected_presenter.sender {
-            let _ = connected_presenter.send(Ok(Message::text(&event)));
-        }
+        item.value().queue(Message::text(&event), delivery);
     });
 }
 
 pub async fn broadcast_to_clients(message: OutgoingUserMessage, users: Users) {
     let event = serde_json::to_string(&message).unwrap();
+    let delivery = message.delivery();
     users.iter().for_each(|item| {
-        let connected_client = item.value();
-        if let Some(ref connected_client) = connected_client.sender {
-            let _ = connected_client.send(Ok(Message::text(&event)));
-        }
+        item.value().queue(Message::text(&event), delivery);
+    });
+}
+
+/// Send a change to the presentation to every user, numbered so clients can
+/// tell if they missed one
+pub fn broadcast_update(message: OutgoingUserMessage, updates: &Updates, users: &Users) {
+    updates.publish(&message, |event| {
+        users.iter().for_each(|item| {
+            item.value().queue(Message::text(event), Delivery::Required);
+        });
     });
 }
 
@@ -37,38 +47,69 @@ pub async fn handle_presenter_message_types(
     presentation: Presentation,
 ) {
     info!("Got presenter message: {presenter_message}");
+    presentation.touch();
+    if presentation.encrypted {
+        if let Err(e) = encryption::check_presenter_message(&presenter_message) {
+            warn!("{} sent unencrypted content: {e}", presenter.identity);
+            presenter.send_ignore_fail(OutgoingPresenterMessage::Error(e));
+            return;
+        }
+    }
+    if presenter_message.is_journaled() {
+        presentation.record(
+            &presenter.identity,
+            JournalEntry::Presenter(presenter_message.clone()),
+        );
+    }
+
     match presenter_message {
         IncomingPresenterMessage::NewSlide(msg) => {
             let mut slide_settings = presentation.slide_settings.write().await;
             *slide_settings = Some(msg.slide_settings.clone());
+            presentation.get_engagement().set_slide(msg.slide);
 
-            broadcast_to_clients(
+            broadcast_update(
                 OutgoingUserMessage::NewSlide(msg.slide_settings),
-                presentation.users,
-            )
-            .await;
+                &presentation.get_updates(),
+                &presentation.users,
+            );
         }
         IncomingPresenterMessage::NewPoll(poll) => {
-            if let Err(existing_poll) = presentation.get_polls().new_poll(poll.clone()) {
+            if let Some(Err(e)) = poll.quiz.as_ref().map(|quiz| quiz.validate(&poll.options)) {
+                let warn = format!("Presenter tried to create an invalid quiz [{}]: {e}", poll.name);
+                warn!("{warn}");
+                presenter.send_ignore_fail(OutgoingPresenterMessage::Error(warn));
+            } else if let Err(existing_poll) = presentation.get_polls().new_poll(poll.clone()) {
                 let warn = format!(
                     "Presenter tried to create poll that already exists: {:?}",
                     &existing_poll
                 );
                 warn!("{warn}");
                 presenter.send_ignore_fail(OutgoingPresenterMessage::Error(warn));
-                broadcast_to_clients(
-                    OutgoingUserMessage::NewPoll(existing_poll),
-                    presentation.users,
-                )
-                .await;
+                broadcast_update(
+                    OutgoingUserMessage::NewPoll(existing_poll.without_answers()),
+                    &presentation.get_updates(),
+                    &presentation.users,
+                );
             } else {
-                broadcast_to_clients(OutgoingUserMessage::NewPoll(poll), presentation.users).await;
+                if poll.duration.is_some() {
+                    presentation.time_poll(poll.name.clone());
+                }
+                broadcast_update(
+                    OutgoingUserMessage::NewPoll(poll.without_answers()),
+                    &presentation.get_updates(),
+                    &presentation.users,
+                );
             }
         }
         IncomingPresenterMessage::GetPollTotals(poll) => {
             let results = presentation.get_polls().get_poll_totals(&poll.name);
-            if let Some(results) = results {
-                presenter.send_ignore_fail(OutgoingPresenterMessage::PollResults(results));
+            if let Some(totals) = results {
+                presenter.send_ignore_fail(OutgoingPresenterMessage::PollResults {
+                    runoff: presentation.get_polls().get_poll_runoff(&poll.name),
+                    name: poll.name,
+                    totals,
+                });
             } else {
                 let warn = format!(
                     "Presenter requested poll results for a poll that does not exist: {}",
@@ -78,13 +119,257 @@ pub async fn handle_presenter_message_types(
                 presenter.send_ignore_fail(OutgoingPresenterMessage::Error(warn));
             }
         }
+        IncomingPresenterMessage::SubscribePollTotals(poll) => {
+            match presentation.get_polls().get_poll_totals(&poll.name) {
+                Some(totals) => {
+                    presentation.get_poll_subscriptions().subscribe(
+                        &poll.name,
+                        &presenter.guid,
+                        poll.window,
+                    );
+                    // Send the current totals right away so the presenter has a starting point
+                    presenter.send_ignore_fail(OutgoingPresenterMessage::PollResults {
+                        runoff: presentation.get_polls().get_poll_runoff(&poll.name),
+                        name: poll.name,
+                        totals,
+                    });
+                }
+                None => {
+                    let warn = format!(
+                        "Presenter tried to subscribe to a poll that does not exist: {}",
+                        poll.name
+                    );
+                    warn!("{warn}");
+                    presenter.send_ignore_fail(OutgoingPresenterMessage::Error(warn));
+                }
+            }
+        }
+        IncomingPresenterMessage::UnsubscribePollTotals(poll) => {
+            presentation
+                .get_poll_subscriptions()
+                .unsubscribe(&poll.name, &presenter.guid);
+        }
+        IncomingPresenterMessage::ClosePoll(poll) => {
+            match presentation.get_polls().close_poll(&poll.name) {
+                Ok(_) => broadcast_update(
+                    OutgoingUserMessage::PollClosed(poll.name),
+                    &presentation.get_updates(),
+                    &presentation.users,
+                ),
+                Err(e) => presenter.send_ignore_fail(OutgoingPresenterMessage::Error(e)),
+            }
+        }
+        IncomingPresenterMessage::ReopenPoll(poll) => {
+            match presentation.get_polls().reopen_poll(&poll.name) {
+                Ok(_) => broadcast_update(
+                    OutgoingUserMessage::PollReopened(poll.name),
+                    &presentation.get_updates(),
+                    &presentation.users,
+                ),
+                Err(e) => presenter.send_ignore_fail(OutgoingPresenterMessage::Error(e)),
+            }
+        }
+        IncomingPresenterMessage::DeletePoll(poll) => {
+            match presentation.get_polls().delete_poll(&poll.name) {
+                Ok(_) => {
+                    presentation.get_poll_subscriptions().remove_poll(&poll.name);
+                    broadcast_update(
+                        OutgoingUserMessage::PollDeleted(poll.name),
+                        &presentation.get_updates(),
+                        &presentation.users,
+                    );
+                }
+                Err(e) => presenter.send_ignore_fail(OutgoingPresenterMessage::Error(e)),
+            }
+        }
+        IncomingPresenterMessage::RevealQuizAnswer(poll) => {
+            let reveal = match presentation.get_polls().reveal_quiz(&poll.name) {
+                Ok(reveal) => reveal,
+                Err(e) => {
+                    warn!("{e}");
+                    presenter.send_ignore_fail(OutgoingPresenterMessage::Error(e));
+                    return;
+                }
+            };
+
+            // Everyone is told the answer, even if they didn't vote
+            for user in presentation.users.iter() {
+                let (correct, points) = reveal
+                    .answers
+                    .get(&user.identity)
+                    .map(|answer| (answer.correct, answer.points))
+                    .unwrap_or((false, 0));
+                user.send_ignore_fail(OutgoingUserMessage::QuizResult {
+                    name: poll.name.clone(),
+                    answer: reveal.correct.clone(),
+                    correct,
+                    points,
+                });
+            }
+
+            presenter.send_ignore_fail(OutgoingPresenterMessage::QuizRevealed {
+                name: poll.name,
+                correct: reveal.correct,
+                voters: reveal.answers.len() as u64,
+                correct_voters: reveal.answers.values().filter(|x| x.correct).count() as u64,
+            });
+        }
+        IncomingPresenterMessage::GetLeaderboard(msg) => {
+            let mut leaderboard = presentation.get_polls().leaderboard();
+            if let Some(limit) = msg.limit {
+                leaderboard.truncate(limit);
+            }
+            presenter.send_ignore_fail(OutgoingPresenterMessage::Leaderboard(leaderboard));
+        }
+        IncomingPresenterMessage::NewPrompt(prompt) => {
+            match presentation.get_prompts().new_prompt(prompt.clone()) {
+                Ok(_) => broadcast_update(
+                    OutgoingUserMessage::NewPrompt(prompt),
+                    &presentation.get_updates(),
+                    &presentation.users,
+                ),
+                Err(e) => {
+                    warn!("{e}");
+                    presenter.send_ignore_fail(OutgoingPresenterMessage::Error(e));
+                }
+            }
+        }
+        IncomingPresenterMessage::SetPromptModeration(msg) => {
+            if let Err(e) = presentation
+                .get_prompts()
+                .set_moderated(&msg.name, msg.moderated)
+            {
+                presenter.send_ignore_fail(OutgoingPresenterMessage::Error(e));
+            }
+        }
+        IncomingPresenterMessage::ModerateResponse(msg) => {
+            let prompts = presentation.get_prompts();
+            match prompts.moderate(&msg.prompt_name, msg.id, msg.approve) {
+                Ok(response) if presentation.encrypted => {
+                    // Encrypted prompts have no word cloud so send the response itself
+                    if response.state == ResponseState::Approved {
+                        broadcast_to_presenters(
+                            OutgoingPresenterMessage::Response {
+                                prompt_name: msg.prompt_name,
+                                id: response.id,
+                                identity: response.identity,
+                                text: response.text,
+                            },
+                            presentation.presenters,
+                        )
+                        .await;
+                    }
+                }
+                Ok(_) => {
+                    // Approving changes the word cloud so send the new one to everyone presenting
+                    if let Some(words) = prompts.get_word_cloud(&msg.prompt_name) {
+                        broadcast_to_presenters(
+                            OutgoingPresenterMessage::WordCloud {
+                                name: msg.prompt_name,
+                                words,
+                            },
+                            presentation.presenters,
+                        )
+                        .await;
+                    }
+                }
+                Err(e) => presenter.send_ignore_fail(OutgoingPresenterMessage::Error(e)),
+            }
+        }
+        IncomingPresenterMessage::GetWordCloud(_) if presentation.encrypted => {
+            presenter.send_ignore_fail(OutgoingPresenterMessage::Error(String::from(
+                "Encrypted presentations don't have word clouds",
+            )));
+        }
+        IncomingPresenterMessage::GetWordCloud(msg) => {
+            match presentation.get_prompts().get_word_cloud(&msg.name) {
+                Some(words) => presenter.send_ignore_fail(OutgoingPresenterMessage::WordCloud {
+                    name: msg.name,
+                    words,
+                }),
+                None => {
+                    let warn = format!(
+                        "Presenter requested a word cloud for a prompt that does not exist: {}",
+                        msg.name
+                    );
+                    warn!("{warn}");
+                    presenter.send_ignore_fail(OutgoingPresenterMessage::Error(warn));
+                }
+            }
+        }
+        IncomingPresenterMessage::GetResponses(msg) => {
+            match presentation.get_prompts().get_approved_responses(&msg.name) {
+                Some(responses) => presenter.send_ignore_fail(OutgoingPresenterMessage::Responses {
+                    name: msg.name,
+                    responses,
+                }),
+                None => presenter.send_ignore_fail(OutgoingPresenterMessage::Error(format!(
+                    "No prompt with name {} exists",
+                    msg.name
+                ))),
+            }
+        }
+        IncomingPresenterMessage::SetQuestionStatus(msg) => {
+            match presentation.get_questions().set_status(msg.id, msg.status) {
+                Ok(_) => question::broadcast_question_queue(&presentation),
+                Err(e) => presenter.send_ignore_fail(OutgoingPresenterMessage::Error(e)),
+            }
+        }
+        IncomingPresenterMessage::GetSlideBreakdown(_) => {
+            presenter.send_ignore_fail(OutgoingPresenterMessage::SlideBreakdown(
+                presentation.get_engagement().slides(),
+            ));
+        }
+        IncomingPresenterMessage::SetEmojiMode(msg) => {
+            let batcher = presentation.get_emoji_batcher();
+            let current = batcher.settings();
+            batcher.configure(EmojiBatchSettings {
+                mode: msg.mode,
+                window: msg.window.unwrap_or(current.window),
+                sample: msg.sample.unwrap_or(current.sample),
+            });
+        }
+        IncomingPresenterMessage::GetConnectionQuality(_) => {
+            presenter.send_ignore_fail(OutgoingPresenterMessage::ConnectionQuality(
+                ConnectionQuality::measure(&presentation, &presenter.guid),
+            ));
+        }
         IncomingPresenterMessage::AddRatelimiter(msg) => {
-            let limiter: Arc<dyn Limiter> = msg.limiter.into();
-            presentation.ratelimiter.add_ratelimit(msg.name, limiter);
+            presentation
+                .ratelimiter
+                .add_configured_ratelimit(msg.name, msg.limiter);
         }
         IncomingPresenterMessage::RemoveRatelimiter(msg) => {
             presentation.ratelimiter.remove_ratelimit(&msg.name);
         }
+        IncomingPresenterMessage::Replay(msg) => {
+            if msg.speed <= 0.0 {
+                presenter.send_ignore_fail(OutgoingPresenterMessage::Error(
+                    "Replay speed must be greater than zero".to_string(),
+                ));
+                return;
+            }
+            // Reading waits for the journal writer and the disk so keep it off
+            // the async workers
+            let events = match presentation.journal.clone() {
+                Some(journal) => {
+                    let id = presentation.id.clone();
+                    tokio::task::spawn_blocking(move || journal.read(&id))
+                        .await
+                        .unwrap_or_else(|e| Err(e.to_string()))
+                }
+                None => Err("This presentation is not being journaled".to_string()),
+            };
+            match events {
+                Ok(events) => {
+                    journal::spawn_replay(journal::current_session(events), presenter, msg.speed)
+                }
+                Err(e) => {
+                    warn!("Could not replay the journal for [{}]: {e}", presentation.id);
+                    presenter.send_ignore_fail(OutgoingPresenterMessage::Error(e));
+                }
+            }
+        }
     }
 }
 
@@ -93,16 +378,18 @@ pub async fn handle_user_message_types(
     user: User,
     presentation: Presentation,
 ) {
+    presentation.touch();
+
     // Run the ratelimiter check
     let ratelimiter_response = presentation
         .ratelimiter
         .check_allowed(user.clone(), &user_message);
 
     // If the connection is still open (should be almost always), send the response
-    if let Some(ref sender) = user.sender {
-        let response =
-            OutgoingUserMessage::RatelimiterResponse(ratelimiter_response.clone()).json();
-        let _ = sender.send(Ok(Message::text(response)));
+    if user.sender.is_some() {
+        user.send_ignore_fail(OutgoingUserMessage::RatelimiterResponse(
+            ratelimiter_response.clone(),
+        ));
     } else {
         error!("{} sent a message from a guid that has no open connection. Dropping message: {user_message}", user.identity);
         return;
@@ -117,10 +404,31 @@ pub async fn handle_user_message_types(
         return;
     }
 
+    if presentation.encrypted {
+        if let Err(e) = encryption::check_user_message(&user_message) {
+            warn!("{} sent unencrypted content: {e}", user.identity);
+            user.send_ignore_fail(OutgoingUserMessage::Error(e));
+            return;
+        }
+    }
+
+    let identity = user.identity.clone();
+    if dispatch_user_message(user_message.clone(), user, &presentation).await {
+        presentation.record(&identity, JournalEntry::User(user_message));
+    }
+}
+
+/// Hand a user message to the processor for its type, skipping the ratelimiter.
+/// Returns whether the message was accepted.
+pub async fn dispatch_user_message(
+    user_message: IncomingUserMessage,
+    user: User,
+    presentation: &Presentation,
+) -> bool {
     match user_message {
         IncomingUserMessage::Emoji(msg) => {
             emoji::handle_user_emoji(
-                &presentation,
+                presentation,
                 user.clone(),
                 msg,
                 presentation.presenters.clone(),
@@ -129,12 +437,36 @@ pub async fn handle_user_message_types(
         }
         IncomingUserMessage::Vote(vote) => {
             vote::handle_user_vote(
-                &presentation,
+                presentation,
                 user.clone(),
                 vote,
                 presentation.presenters.clone(),
             )
             .await
         }
+        IncomingUserMessage::TextResponse(response) => {
+            prompt::handle_user_text_response(
+                presentation,
+                user.clone(),
+                response,
+                presentation.presenters.clone(),
+            )
+            .await
+        }
+        IncomingUserMessage::Question(msg) => {
+            question::handle_user_question(presentation, user.clone(), msg).await
+        }
+        IncomingUserMessage::UpvoteQuestion(msg) => {
+            question::handle_user_upvote(presentation, user.clone(), msg).await
+        }
+        IncomingUserMessage::RetractVote(retract) => {
+            vote::handle_user_retract_vote(
+                presentation,
+                user.clone(),
+                retract,
+                presentation.presenters.clone(),
+            )
+            .await
+        }
     }
 }
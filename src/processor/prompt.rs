@@ -0,0 +1,70 @@
+use crate::{
+    presentation::ResponseState, OutgoingPresenterMessage, OutgoingUserMessage, Presentation,
+    Presenters, TextResponseMessage, User,
+};
+
+/// Called from the processor system. Only one processor should be called per user message
+/// which is in a separate tokio task. Again this means we do not need to start tokio tasks
+/// to unblock processesing of further user messages.
+///
+/// Returns whether the response was accepted.
+pub async fn handle_user_text_response(
+    presentation: &Presentation,
+    user: User,
+    response: TextResponseMessage,
+    presenters: Presenters,
+) -> bool {
+    let result = presentation.get_prompts().respond(
+        &response.prompt_name,
+        &user.identity,
+        &response.text,
+        presentation.encrypted,
+    );
+
+    let recorded = match result {
+        Ok(recorded) => recorded,
+        Err(e) => {
+            user.send_ignore_fail(OutgoingUserMessage::Error(e));
+            return false;
+        }
+    };
+
+    info!(
+        "{} responded to [{}] with [{}]",
+        user.identity, response.prompt_name, recorded.text
+    );
+
+    if recorded.state == ResponseState::Pending {
+        user.send_ignore_fail(OutgoingUserMessage::Success(String::from(
+            "Response awaiting approval",
+        )));
+        super::broadcast_to_presenters(
+            OutgoingPresenterMessage::PendingResponse {
+                prompt_name: response.prompt_name,
+                id: recorded.id,
+                identity: recorded.identity,
+                text: recorded.text,
+            },
+            presenters,
+        )
+        .await;
+    } else {
+        user.send_ignore_fail(OutgoingUserMessage::Success(String::from(
+            "Response recorded",
+        )));
+        // Encrypted prompts have no word cloud so presenters get each response
+        if presentation.encrypted {
+            super::broadcast_to_presenters(
+                OutgoingPresenterMessage::Response {
+                    prompt_name: response.prompt_name,
+                    id: recorded.id,
+                    identity: recorded.identity,
+                    text: recorded.text,
+                },
+                presenters,
+            )
+            .await;
+        }
+    }
+    true
+}
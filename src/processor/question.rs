@@ -0,0 +1,47 @@
+use crate::{OutgoingUserMessage, Presentation, QuestionMessage, UpvoteQuestionMessage, User};
+
+/// Send the sorted question queue to everyone. Changes are collected so
+/// presenters get it at most every quarter second and users at most once a
+/// second.
+pub fn broadcast_question_queue(presentation: &Presentation) {
+    presentation
+        .get_questions()
+        .changed(presentation.presenters.clone(), presentation.users.clone());
+}
+
+/// Called from the processor system. Only one processor should be called per user message
+/// which is in a separate tokio task. Again this means we do not need to start tokio tasks
+/// to unblock processesing of further user messages.
+///
+/// Returns whether the question was accepted.
+pub async fn handle_user_question(presentation: &Presentation, user: User, question: QuestionMessage) -> bool {
+    match presentation.get_questions().ask(&user.identity, &question.text, presentation.encrypted) {
+        Ok(id) => {
+            info!("{} asked question {id}: {}", user.identity, question.text);
+            user.send_ignore_fail(OutgoingUserMessage::Success(String::from("Question asked")));
+            presentation
+                .get_engagement()
+                .question_asked(presentation.presenters.clone());
+            broadcast_question_queue(presentation);
+            true
+        }
+        Err(e) => {
+            user.send_ignore_fail(OutgoingUserMessage::Error(e));
+            false
+        }
+    }
+}
+
+pub async fn handle_user_upvote(presentation: &Presentation, user: User, upvote: UpvoteQuestionMessage) -> bool {
+    match presentation.get_questions().upvote(&user.identity, upvote.id) {
+        Ok(_) => {
+            debug!("{} upvoted question {}", user.identity, upvote.id);
+            broadcast_question_queue(presentation);
+            true
+        }
+        Err(e) => {
+            user.send_ignore_fail(OutgoingUserMessage::Error(e));
+            false
+        }
+    }
+}
@@ -1,23 +1,72 @@
 use crate::{
-    Presentation, Presenters, User, Vote, presentation::IdentifiedVote
+    presentation::{IdentifiedVote, VoteOutcome},
+    OutgoingUserMessage, Presentation, Presenters, RetractVoteMessage, User, Vote,
 };
 
 /// Called from the processor system. Only one processor should be called per user message
 /// which is in a separate tokio task. Again this means we do not need to start tokio tasks
 /// to unblock processesing of further user messages.
+///
+/// Returns whether the vote was accepted.
 pub async fn handle_user_vote(
     presentation: &Presentation,
     user: User,
     vote: Vote,
-    _presenters: Presenters,
-) {
+    presenters: Presenters,
+) -> bool {
     let identified_vote = IdentifiedVote {
         identity: user.identity.clone(),
         vote,
     };
+    let poll_name = identified_vote.vote.poll_name().to_string();
 
     match presentation.get_polls().vote_in_poll(identified_vote) {
-        Ok(_) => user.send_ignore_fail(crate::OutgoingUserMessage::Success(String::from("Vote recorded"))),
-        Err(e) => user.send_ignore_fail(crate::OutgoingUserMessage::Error(e)),
+        Ok(outcome) => {
+            match outcome {
+                VoteOutcome::Recorded => user.send_ignore_fail(OutgoingUserMessage::Success(String::from("Vote recorded"))),
+                VoteOutcome::Replaced => user.send_ignore_fail(OutgoingUserMessage::VoteReplaced(poll_name.clone())),
+            }
+            presentation.get_engagement().vote_cast(presenters.clone());
+            // Let any presenters watching this poll know the totals have moved
+            presentation
+                .get_poll_subscriptions()
+                .poll_changed(&poll_name, presentation.get_polls(), presenters);
+            true
+        }
+        Err(e) => {
+            user.send_ignore_fail(OutgoingUserMessage::Error(format!("Could not vote in {poll_name}: {e}")));
+            false
+        }
     }
-}
\ No newline at end of file
+}
+
+/// Called from the processor system when a user wants to withdraw a vote they have
+/// already cast. Whether this is allowed is decided by the poll's vote policy.
+pub async fn handle_user_retract_vote(
+    presentation: &Presentation,
+    user: User,
+    retract: RetractVoteMessage,
+    presenters: Presenters,
+) -> bool {
+    match presentation
+        .get_polls()
+        .retract_vote(&user.identity, &retract.poll_name)
+    {
+        Ok(_) => {
+            presentation.get_poll_subscriptions().poll_changed(
+                &retract.poll_name,
+                presentation.get_polls(),
+                presenters,
+            );
+            user.send_ignore_fail(OutgoingUserMessage::VoteWithdrawn(retract.poll_name));
+            true
+        }
+        Err(e) => {
+            user.send_ignore_fail(OutgoingUserMessage::Error(format!(
+                "Could not withdraw vote in {}: {e}",
+                retract.poll_name
+            )));
+            false
+        }
+    }
+}
@@ -4,20 +4,20 @@ use std::sync::Arc;
 use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
 
-use crate::{Client, IncomingUserMessage, OutgoingUserMessage, User};
+use crate::{metrics, Client, IncomingUserMessage, OutgoingUserMessage, User};
 
 pub mod time;
 pub mod value;
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub enum LimiterType {
     Time(time::TimeLimiter),
     Value(value::ValueLimiter),
 }
 
-impl Into<Arc<dyn Limiter>> for LimiterType {
-    fn into(self) -> Arc<dyn Limiter> {
-        match self {
+impl From<LimiterType> for Arc<dyn Limiter> {
+    fn from(limiter: LimiterType) -> Self {
+        match limiter {
             LimiterType::Time(limiter) => Arc::new(limiter),
             LimiterType::Value(limiter) => Arc::new(limiter),
         }
@@ -58,8 +58,10 @@ pub trait Limiter: Send + Sync {
 #[derive(Clone)]
 pub struct Ratelimiter {
     limiters: DashMap<String, Arc<dyn Limiter>>,
+    configurations: DashMap<String, LimiterType>,
     limiter_data: DashMap<String, u64>,
     global_data: DashMap<String, u64>,
+    blocks: DashMap<String, u64>,
 }
 
 impl Default for Ratelimiter {
@@ -74,6 +76,10 @@ impl Ratelimiter {
             // Contains all the configured limiters
             limiters: DashMap::default(),
 
+            // The configuration each limiter was created from, if it was
+            // created from one, so the ratelimiter can be saved and restored
+            configurations: DashMap::default(),
+
             // Contains the data for all the configured limiters. Limiters
             // are never given write access to this data and updates must be
             // done by the Ratelimiter
@@ -83,18 +89,46 @@ impl Ratelimiter {
             // data that is useful to many limiters such as last time a message
             // was successfully sent
             global_data: DashMap::default(),
+
+            // How many messages each limiter has blocked
+            blocks: DashMap::default(),
         }
     }
 
     /// Adds a ratelimit to the ratelimiter. If a ratelimit with that name
     /// is already present it replaces it.
     pub fn add_ratelimit(&self, name: String, limit: Arc<dyn Limiter>) {
+        self.configurations.remove(&name);
         self.limiters.insert(name, limit);
     }
 
+    /// Adds a ratelimit from its configuration. Unlike `add_ratelimit` the
+    /// configuration is kept so the limiter is included in snapshots.
+    pub fn add_configured_ratelimit(&self, name: String, limiter: LimiterType) {
+        self.limiters.insert(name.clone(), limiter.clone().into());
+        self.configurations.insert(name, limiter);
+    }
+
     /// Remove a limiter from the ratelimiter system
     pub fn remove_ratelimit(&self, name: &str) {
         self.limiters.remove(name);
+        self.configurations.remove(name);
+    }
+
+    /// How many messages each limiter has blocked
+    pub fn block_counts(&self) -> HashMap<String, u64> {
+        self.blocks
+            .iter()
+            .map(|x| (x.key().clone(), *x.value()))
+            .collect()
+    }
+
+    /// The configuration of every limiter that was added from one
+    pub fn configurations(&self) -> HashMap<String, LimiterType> {
+        self.configurations
+            .iter()
+            .map(|x| (x.key().clone(), x.value().clone()))
+            .collect()
     }
 
     pub fn check_allowed(
@@ -130,12 +164,17 @@ impl Ratelimiter {
             );
             match update {
                 Ok(update) => updates.insert(item.key().to_string(), update),
-                Err(e) => return RatelimiterResponse::Blocked(e),
+                Err(e) => {
+                    *self.blocks.entry(item.key().to_string()).or_insert(0) += 1;
+                    metrics::ratelimiter_checked(&client.presentation, item.key(), false);
+                    return RatelimiterResponse::Blocked(e);
+                }
             };
         }
 
         // Update all the limiters now that none of them are blocking
         for (name, update) in &updates {
+            metrics::ratelimiter_checked(&client.presentation, name, true);
             if let Some(ref update) = update.limiter_data_update {
                 self.limiter_data
                     .insert(format!("{name}-{}", update.data), update.value);
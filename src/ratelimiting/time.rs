@@ -1,11 +1,11 @@
 use dashmap::DashMap;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::{IncomingUserMessage, User};
 
 use super::{Limiter, LimiterUpdate};
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct TimeLimiter {
     interval: u64,
 }
@@ -1,17 +1,20 @@
 use dashmap::DashMap;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::{ratelimiting::LimiterDataUpdate, EmojiMessage, IncomingUserMessage, User};
 
 use super::{Limiter, LimiterUpdate};
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct ValueLimiter {
     small_cost: u64,
     large_cost: u64,
     huge_cost: u64,
     points_per_10: u64,
     max_points: u64,
+    /// What asking a question costs. Questions are free if this is not set.
+    #[serde(default)]
+    question_cost: u64,
 }
 
 impl ValueLimiter {
@@ -21,6 +24,7 @@ impl ValueLimiter {
         huge_cost: u64,
         points_per_10: u64,
         max_points: u64,
+        question_cost: u64,
     ) -> Self {
         Self {
             small_cost,
@@ -28,6 +32,7 @@ impl ValueLimiter {
             huge_cost,
             points_per_10,
             max_points,
+            question_cost,
         }
     }
 }
@@ -50,9 +55,16 @@ impl Limiter for ValueLimiter {
             IncomingUserMessage::Emoji(EmojiMessage { size, .. }) => {
                 return Err(format!("{identity} sent emoji with invalid size: {size}"))
             }
+            IncomingUserMessage::Question(_) => self.question_cost,
             // Value limiter does not care about votes because ideally everyone votes
             // #democracy
-            IncomingUserMessage::Vote(_) => return Ok(LimiterUpdate::default()),
+            // Text responses are limited to one per prompt so they are free too
+            IncomingUserMessage::Vote(_)
+            | IncomingUserMessage::RetractVote(_)
+            | IncomingUserMessage::TextResponse(_)
+            | IncomingUserMessage::UpvoteQuestion(_) => {
+                return Ok(LimiterUpdate::default())
+            }
         };
         // If they've never sent a message then it's whatever their starting balance is
         let existing_balance = data
@@ -67,7 +79,10 @@ impl Limiter for ValueLimiter {
         );
 
         if message_cost > new_balance {
-            return Err("Emoji too expensive".to_string());
+            return Err(match message {
+                IncomingUserMessage::Question(_) => "Question too expensive".to_string(),
+                _ => "Emoji too expensive".to_string(),
+            });
         }
         debug!(
             "{identity} has new reaction balance of {}",
@@ -0,0 +1,91 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use jsonwebtoken::Algorithm;
+use serde::{Deserialize, Serialize};
+
+use super::EngagementReport;
+use crate::{authentication::VerificationKey, storage::SharedStorage, Presentation};
+
+/// The most ended presentations whose reports are kept in memory when there
+/// is no storage. The oldest are forgotten first.
+const MAX_REPORTS_IN_MEMORY: usize = 1000;
+
+/// The report of a presentation that has ended, along with what's needed to
+/// check that whoever asks for it is the presenter
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ArchivedReport {
+    pub presenter_identity: String,
+    pub authentication_key_source: String,
+    pub authentication_algorithms: Vec<Algorithm>,
+    pub report: EngagementReport,
+}
+
+impl ArchivedReport {
+    pub fn new(presentation: &Presentation) -> Self {
+        Self {
+            presenter_identity: presentation.presenter_identity.clone(),
+            authentication_key_source: presentation.authentication_key_source.clone(),
+            authentication_algorithms: presentation.authentication_algorithms.clone(),
+            report: EngagementReport::new(presentation),
+        }
+    }
+
+    /// The key the presenter's tokens were signed with
+    pub fn verification_key(&self) -> Result<VerificationKey, String> {
+        VerificationKey::parse(&self.authentication_key_source)
+            .and_then(|key| key.with_algorithms(&self.authentication_algorithms))
+    }
+}
+
+/// Reports of presentations that have ended, so presenters can still get
+/// them afterwards. They are saved to storage if there is any, otherwise
+/// the most recent ones are kept in memory.
+#[derive(Clone)]
+pub struct Reports {
+    storage: Option<SharedStorage>,
+    in_memory: Arc<DashMap<String, ArchivedReport>>,
+}
+
+impl Reports {
+    pub fn new(storage: Option<SharedStorage>) -> Self {
+        Self {
+            storage,
+            in_memory: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// Build and keep the final report of a presentation that is ending
+    pub fn archive(&self, presentation: &Presentation) {
+        let archived = ArchivedReport::new(presentation);
+
+        if let Some(ref storage) = self.storage {
+            if let Err(e) = storage.save_report(&archived) {
+                error!("Could not save the report for [{}]: {e}", presentation.id);
+            }
+            return;
+        }
+
+        if self.in_memory.len() >= MAX_REPORTS_IN_MEMORY {
+            let oldest = self
+                .in_memory
+                .iter()
+                .min_by_key(|x| x.report.generated_at)
+                .map(|x| x.key().clone());
+            if let Some(oldest) = oldest {
+                self.in_memory.remove(&oldest);
+            }
+        }
+        self.in_memory.insert(presentation.id.clone(), archived);
+    }
+
+    pub fn get(&self, presentation_id: &str) -> Option<ArchivedReport> {
+        match self.storage {
+            Some(ref storage) => storage.load_report(presentation_id).unwrap_or_else(|e| {
+                error!("Could not load the report for [{presentation_id}]: {e}");
+                None
+            }),
+            None => self.in_memory.get(presentation_id).map(|x| x.value().clone()),
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::fmt::Write;
+
+use super::EngagementReport;
+
+/// Quote a field if it contains anything that would break the row
+fn field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn row(out: &mut String, section: &str, name: &str, item: &str, value: impl std::fmt::Display) {
+    let _ = writeln!(
+        out,
+        "{},{},{},{value}",
+        field(section),
+        field(name),
+        field(item)
+    );
+}
+
+/// Flatten the report into one table so it opens in any spreadsheet. The
+/// `section` column says what each row is about.
+pub fn render(report: &EngagementReport) -> String {
+    let mut out = String::from("section,name,item,value\n");
+
+    row(&mut out, "presentation", "title", "", field(&report.title));
+    row(&mut out, "presentation", "started_at", "", report.started_at);
+    row(&mut out, "presentation", "generated_at", "", report.generated_at);
+    row(&mut out, "audience", "peak", "", report.audience.peak);
+    row(&mut out, "audience", "average", "", format!("{:.2}", report.audience.average));
+
+    for (emoji, count) in &report.emoji_totals {
+        row(&mut out, "emoji", emoji, "", count);
+    }
+    for activity in &report.slides {
+        let slide = activity.slide.to_string();
+        for (emoji, count) in &activity.emojis {
+            row(&mut out, "slide_emoji", &slide, emoji, count);
+        }
+        row(&mut out, "slide_votes", &slide, "", activity.votes);
+        row(&mut out, "slide_questions", &slide, "", activity.questions);
+    }
+
+    for poll in &report.polls {
+        row(&mut out, "poll_voters", &poll.name, "", poll.voters);
+        for option in &poll.options {
+            let (count, sum) = poll
+                .totals
+                .get(option)
+                .map(|totals| (totals.count, totals.sum))
+                .unwrap_or((0, 0));
+            row(&mut out, "poll_count", &poll.name, option, count);
+            row(&mut out, "poll_sum", &poll.name, option, sum);
+        }
+        if let Some(winner) = poll.runoff.as_ref().and_then(|x| x.winner.as_ref()) {
+            row(&mut out, "poll_winner", &poll.name, winner, 1);
+        }
+    }
+
+    for (name, blocked) in &report.ratelimiter_blocks {
+        row(&mut out, "ratelimiter_blocks", name, "", blocked);
+    }
+
+    let bucket = report.reactions.bucket_seconds;
+    for (index, count) in report.reactions.counts.iter().enumerate() {
+        row(
+            &mut out,
+            "reactions",
+            &(index as u64 * bucket).to_string(),
+            &bucket.to_string(),
+            count,
+        );
+    }
+
+    out
+}
@@ -0,0 +1,178 @@
+use std::fmt::Write;
+
+use super::EngagementReport;
+
+const CHART_WIDTH: u64 = 640;
+const BAR_HEIGHT: u64 = 22;
+const LABEL_WIDTH: u64 = 160;
+
+const STYLE: &str = "body{font-family:sans-serif;max-width:720px;margin:2em auto;color:#222}\
+h1{margin-bottom:0}.subtitle{color:#666;margin-top:.2em}\
+.stats{display:flex;gap:2em}.stat b{display:block;font-size:2em}\
+svg{display:block;margin:1em 0}svg text{font-size:12px}\
+.bar{fill:#4a7bd0}.axis{stroke:#999}\
+table{border-collapse:collapse}td,th{padding:.2em .8em;text-align:left;border-bottom:1px solid #ddd}";
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// A horizontal bar chart with one labelled bar per item
+fn bar_chart(out: &mut String, items: &[(String, u64)]) {
+    let max = items.iter().map(|x| x.1).max().unwrap_or(0).max(1);
+    let height = items.len() as u64 * BAR_HEIGHT;
+    let bar_space = CHART_WIDTH - LABEL_WIDTH - 50;
+
+    let _ = write!(out, r#"<svg width="{CHART_WIDTH}" height="{height}">"#);
+    for (index, (label, value)) in items.iter().enumerate() {
+        let y = index as u64 * BAR_HEIGHT;
+        let width = bar_space * value / max;
+        let _ = write!(
+            out,
+            r#"<text x="{}" y="{}" text-anchor="end">{}</text><rect class="bar" x="{LABEL_WIDTH}" y="{}" width="{width}" height="{}"/><text x="{}" y="{}">{value}</text>"#,
+            LABEL_WIDTH - 6,
+            y + 15,
+            escape(label),
+            y + 3,
+            BAR_HEIGHT - 6,
+            LABEL_WIDTH + width + 4,
+            y + 15,
+        );
+    }
+    out.push_str("</svg>");
+}
+
+/// A column chart of reactions over time
+fn time_chart(out: &mut String, counts: &[u64], bucket_seconds: u64) {
+    let height = 160;
+    let max = counts.iter().copied().max().unwrap_or(0).max(1);
+    let column = (CHART_WIDTH as f64 / counts.len().max(1) as f64).max(1.0);
+
+    let _ = write!(out, r#"<svg width="{CHART_WIDTH}" height="{}">"#, height + 20);
+    for (index, count) in counts.iter().enumerate() {
+        let bar = (height * count / max) as f64;
+        let _ = write!(
+            out,
+            r#"<rect class="bar" x="{:.1}" y="{:.1}" width="{:.1}" height="{bar:.1}"><title>{} min: {count}</title></rect>"#,
+            index as f64 * column,
+            height as f64 - bar,
+            (column - 1.0).max(1.0),
+            index as u64 * bucket_seconds / 60,
+        );
+    }
+    let minutes = counts.len() as u64 * bucket_seconds / 60;
+    let _ = write!(
+        out,
+        r#"<line class="axis" x1="0" y1="{height}" x2="{CHART_WIDTH}" y2="{height}"/><text x="0" y="{}">0 min</text><text x="{CHART_WIDTH}" y="{}" text-anchor="end">{minutes} min</text></svg>"#,
+        height + 15,
+        height + 15,
+    );
+}
+
+/// A single page with everything inlined so it can be saved and shared
+/// without the server
+pub fn render(report: &EngagementReport) -> String {
+    let mut out = String::new();
+    let title = escape(&report.title);
+    let minutes = report.generated_at.saturating_sub(report.started_at) / 60_000;
+
+    let _ = write!(
+        out,
+        r#"<!DOCTYPE html><html><head><meta charset="utf-8"><title>{title} - Engagement report</title><style>{STYLE}</style></head><body>"#
+    );
+    let _ = write!(
+        out,
+        r#"<h1>{title}</h1><p class="subtitle">Engagement report covering {minutes} minutes</p>"#
+    );
+
+    let total_emojis: u64 = report.emoji_totals.values().sum();
+    let _ = write!(
+        out,
+        r#"<div class="stats"><div class="stat"><b>{}</b>peak audience</div><div class="stat"><b>{:.1}</b>average audience</div><div class="stat"><b>{total_emojis}</b>reactions</div></div>"#,
+        report.audience.peak, report.audience.average
+    );
+
+    out.push_str("<h2>Reactions over time</h2>");
+    time_chart(
+        &mut out,
+        &report.reactions.counts,
+        report.reactions.bucket_seconds,
+    );
+
+    if !report.emoji_totals.is_empty() {
+        out.push_str("<h2>Reactions by emoji</h2>");
+        let items: Vec<(String, u64)> = report
+            .emoji_totals
+            .iter()
+            .map(|(emoji, count)| (emoji.clone(), *count))
+            .collect();
+        bar_chart(&mut out, &items);
+    }
+
+    if !report.slides.is_empty() {
+        out.push_str("<h2>Activity by slide</h2>");
+        let items: Vec<(String, u64)> = report
+            .slides
+            .iter()
+            .map(|x| (format!("Slide {}", x.slide), x.total_emojis() + x.votes + x.questions))
+            .collect();
+        bar_chart(&mut out, &items);
+
+        out.push_str("<table><tr><th>Slide</th><th>Reactions</th><th>Votes</th><th>Questions</th></tr>");
+        for activity in &report.slides {
+            let summary: Vec<String> = activity
+                .emojis
+                .iter()
+                .map(|(emoji, count)| format!("{} {count}", escape(emoji)))
+                .collect();
+            let _ = write!(
+                out,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                activity.slide,
+                summary.join(", "),
+                activity.votes,
+                activity.questions
+            );
+        }
+        out.push_str("</table>");
+    }
+
+    if !report.polls.is_empty() {
+        out.push_str("<h2>Polls</h2>");
+        for poll in &report.polls {
+            let _ = write!(
+                out,
+                "<h3>{}</h3><p>{} voters</p>",
+                escape(&poll.name),
+                poll.voters
+            );
+            let items: Vec<(String, u64)> = poll
+                .options
+                .iter()
+                .map(|option| {
+                    let count = poll.totals.get(option).map(|x| x.count).unwrap_or(0);
+                    (option.clone(), count)
+                })
+                .collect();
+            bar_chart(&mut out, &items);
+            if let Some(winner) = poll.runoff.as_ref().and_then(|x| x.winner.as_ref()) {
+                let _ = write!(out, "<p>Winner after instant-runoff: {}</p>", escape(winner));
+            }
+        }
+    }
+
+    if !report.ratelimiter_blocks.is_empty() {
+        out.push_str("<h2>Ratelimiting</h2><table><tr><th>Limiter</th><th>Messages blocked</th></tr>");
+        for (name, blocked) in &report.ratelimiter_blocks {
+            let _ = write!(out, "<tr><td>{}</td><td>{blocked}</td></tr>", escape(name));
+        }
+        out.push_str("</table>");
+    }
+
+    out.push_str("</body></html>");
+    out
+}
@@ -0,0 +1,222 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    presentation::{now_millis, ChoiceTotals, RunoffResults, SlideActivity},
+    Presentation,
+};
+
+mod archive;
+mod csv;
+mod html;
+
+pub use archive::{ArchivedReport, Reports};
+
+/// The reaction series is never split into more buckets than this
+const MAX_REACTION_BUCKETS: u64 = 120;
+
+/// The reaction series buckets are a multiple of this many seconds
+const MIN_REACTION_BUCKET: u64 = 10;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AudienceReport {
+    pub peak: u64,
+    pub average: f64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PollReport {
+    pub name: String,
+    pub open: bool,
+    pub voters: u64,
+    pub totals: HashMap<String, ChoiceTotals>,
+    /// The poll's options in the order the presenter gave them
+    pub options: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub runoff: Option<RunoffResults>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ReactionSeries {
+    /// How many seconds each bucket covers
+    pub bucket_seconds: u64,
+    /// Emojis sent in each bucket, starting when the presentation was created
+    pub counts: Vec<u64>,
+}
+
+/// Everything a speaker wants to know about how their talk went
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EngagementReport {
+    pub id: String,
+    pub title: String,
+    /// Milliseconds since the epoch
+    pub started_at: u64,
+    /// Milliseconds since the epoch
+    pub generated_at: u64,
+    pub audience: AudienceReport,
+    pub slides: Vec<SlideActivity>,
+    pub emoji_totals: BTreeMap<String, u64>,
+    pub polls: Vec<PollReport>,
+    /// How many messages each ratelimiter blocked
+    pub ratelimiter_blocks: BTreeMap<String, u64>,
+    pub reactions: ReactionSeries,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum ReportFormat {
+    Json,
+    Csv,
+    Html,
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            "html" => Ok(Self::Html),
+            _ => Err(format!("Unknown report format: {s}")),
+        }
+    }
+}
+
+impl ReportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Html => "text/html; charset=utf-8",
+        }
+    }
+}
+
+impl EngagementReport {
+    pub fn new(presentation: &Presentation) -> Self {
+        let engagement = presentation.get_engagement();
+        let generated_at = now_millis();
+
+        // Pick a bucket size that keeps the series a readable length
+        let duration = generated_at.saturating_sub(engagement.started_at()) / 1000;
+        let bucket_seconds = (duration / MAX_REACTION_BUCKETS / MIN_REACTION_BUCKET + 1)
+            * MIN_REACTION_BUCKET;
+
+        let polls = presentation
+            .get_polls()
+            .all()
+            .into_iter()
+            .map(|poll| PollReport {
+                name: poll.definition().name.clone(),
+                open: poll.is_open(),
+                voters: poll.voters(),
+                totals: poll.totals(),
+                options: poll.definition().options.clone(),
+                runoff: poll.runoff(),
+            })
+            .collect();
+
+        Self {
+            id: presentation.id.clone(),
+            title: presentation.get_title(),
+            started_at: engagement.started_at(),
+            generated_at,
+            audience: AudienceReport {
+                peak: engagement.peak_audience(),
+                average: engagement.average_audience(),
+            },
+            slides: engagement.slides(),
+            emoji_totals: engagement.emoji_totals(),
+            polls,
+            ratelimiter_blocks: presentation.ratelimiter.block_counts().into_iter().collect(),
+            reactions: ReactionSeries {
+                bucket_seconds,
+                counts: engagement.reactions(bucket_seconds),
+            },
+        }
+    }
+
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Json => serde_json::to_string(self).unwrap_or_default(),
+            ReportFormat::Csv => csv::render(self),
+            ReportFormat::Html => html::render(self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TITLE: &str = r#"Q&A, "live" <script>alert('hi')</script>"#;
+    const POLL: &str = "Tabs, or spaces?";
+    const OPTION: &str = "Spaces\n<b>obviously</b>";
+
+    /// A report where everyone wrote something awkward
+    fn report() -> EngagementReport {
+        let totals = ChoiceTotals {
+            sum: 3,
+            count: 3,
+            min: 1,
+            max: 1,
+            mean: 1.0,
+        };
+        EngagementReport {
+            id: "presentation".to_string(),
+            title: TITLE.to_string(),
+            started_at: 0,
+            generated_at: 60_000,
+            audience: AudienceReport {
+                peak: 3,
+                average: 2.0,
+            },
+            slides: vec![SlideActivity {
+                slide: 1,
+                emojis: BTreeMap::from([("<3".to_string(), 2)]),
+                votes: 3,
+                questions: 0,
+            }],
+            emoji_totals: BTreeMap::from([("<3".to_string(), 2)]),
+            polls: vec![PollReport {
+                name: POLL.to_string(),
+                open: false,
+                voters: 3,
+                totals: HashMap::from([(OPTION.to_string(), totals)]),
+                options: vec![OPTION.to_string()],
+                runoff: None,
+            }],
+            ratelimiter_blocks: BTreeMap::new(),
+            reactions: ReactionSeries {
+                bucket_seconds: 10,
+                counts: vec![2],
+            },
+        }
+    }
+
+    #[test]
+    fn csv_fields_are_quoted() {
+        let csv = report().render(ReportFormat::Csv);
+
+        assert!(csv.contains(
+            r#"presentation,title,,"Q&A, ""live"" <script>alert('hi')</script>""#
+        ));
+        assert!(csv.contains("poll_voters,\"Tabs, or spaces?\",,3\n"));
+        assert!(csv.contains("poll_count,\"Tabs, or spaces?\",\"Spaces\n<b>obviously</b>\",3\n"));
+        assert!(csv.contains("slide_emoji,1,<3,2\n"));
+    }
+
+    #[test]
+    fn html_is_escaped() {
+        let html = report().render(ReportFormat::Html);
+
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("<b>obviously"));
+        assert!(html.contains(
+            "<title>Q&amp;A, &quot;live&quot; &lt;script&gt;alert(&#39;hi&#39;)&lt;/script&gt; - Engagement report</title>"
+        ));
+        assert!(html.contains("Spaces\n&lt;b&gt;obviously&lt;/b&gt;"));
+        assert!(html.contains("&lt;3 2"));
+    }
+}
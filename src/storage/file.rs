@@ -0,0 +1,113 @@
+use std::path::{Path, PathBuf};
+
+use super::Storage;
+use crate::{presentation::PresentationSnapshot, report::ArchivedReport};
+
+/// Stores each presentation as a JSON file in a directory, and the reports
+/// of ended presentations in a `reports` directory inside it
+pub struct FileStorage {
+    directory: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(directory: impl Into<PathBuf>) -> Result<Self, String> {
+        let directory = directory.into();
+        std::fs::create_dir_all(directory.join("reports")).map_err(|e| {
+            format!(
+                "Could not create storage directory {}: {e}",
+                directory.display()
+            )
+        })?;
+        Ok(Self { directory })
+    }
+
+    fn path(&self, presentation_id: &str) -> PathBuf {
+        file_path(&self.directory, presentation_id)
+    }
+
+    fn report_path(&self, presentation_id: &str) -> PathBuf {
+        file_path(&self.directory.join("reports"), presentation_id)
+    }
+}
+
+/// Presentation ids come from JWTs so they can't be trusted as file
+/// names. Hex encoding them means they can never escape the directory.
+fn file_path(directory: &Path, presentation_id: &str) -> PathBuf {
+    let name: String = presentation_id
+        .bytes()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    directory.join(format!("{name}.json"))
+}
+
+/// Write to a temporary file first so a crash mid write never leaves a
+/// half written file behind
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), String> {
+    let temporary = path.with_extension("json.tmp");
+    std::fs::write(&temporary, contents).map_err(|e| e.to_string())?;
+    std::fs::rename(&temporary, path).map_err(|e| e.to_string())
+}
+
+impl Storage for FileStorage {
+    fn save(&self, snapshot: &PresentationSnapshot) -> Result<(), String> {
+        let contents = serde_json::to_vec(snapshot).map_err(|e| e.to_string())?;
+        write_atomically(&self.path(&snapshot.id), &contents)
+    }
+
+    fn load_all(&self) -> Result<Vec<PresentationSnapshot>, String> {
+        let entries = std::fs::read_dir(&self.directory).map_err(|e| e.to_string())?;
+
+        let mut snapshots = vec![];
+        for entry in entries {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            if path.extension().and_then(|x| x.to_str()) != Some("json") {
+                continue;
+            }
+
+            let loaded = std::fs::read(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|x| serde_json::from_slice(&x).map_err(|e| e.to_string()));
+            match loaded {
+                Ok(snapshot) => snapshots.push(snapshot),
+                Err(e) => error!("Could not load snapshot {}: {e}", path.display()),
+            }
+        }
+
+        Ok(snapshots)
+    }
+
+    fn remove(&self, presentation_id: &str) -> Result<(), String> {
+        match std::fs::remove_file(self.path(presentation_id)) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn save_report(&self, report: &ArchivedReport) -> Result<(), String> {
+        let contents = serde_json::to_vec(report).map_err(|e| e.to_string())?;
+        write_atomically(&self.report_path(&report.report.id), &contents)
+    }
+
+    fn load_report(&self, presentation_id: &str) -> Result<Option<ArchivedReport>, String> {
+        match std::fs::read(self.report_path(presentation_id)) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .map(Some)
+                .map_err(|e| e.to_string()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn check(&self) -> Result<(), String> {
+        check_writable(&self.directory)
+    }
+}
+
+/// Write and remove a probe file to prove a directory can still be written to
+pub(crate) fn check_writable(directory: &std::path::Path) -> Result<(), String> {
+    let probe = directory.join(".probe");
+    std::fs::write(&probe, b"")
+        .and_then(|_| std::fs::remove_file(&probe))
+        .map_err(|e| format!("{} is not writable: {e}", directory.display()))
+}
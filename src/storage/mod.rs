@@ -0,0 +1,116 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use crate::{
+    presentation::PresentationSnapshot, report::ArchivedReport, Presentation, Presentations,
+};
+
+pub mod file;
+
+pub use file::FileStorage;
+
+pub type SharedStorage = Arc<dyn Storage>;
+
+/// A place presentation snapshots can be saved to and loaded back from
+pub trait Storage: Send + Sync {
+    /// Save a snapshot, replacing any earlier snapshot of the same presentation
+    fn save(&self, snapshot: &PresentationSnapshot) -> Result<(), String>;
+
+    /// Load every snapshot that has been saved
+    fn load_all(&self) -> Result<Vec<PresentationSnapshot>, String>;
+
+    /// Remove the snapshot of a presentation if there is one
+    fn remove(&self, presentation_id: &str) -> Result<(), String>;
+
+    /// Keep the report of a presentation that has ended, replacing any
+    /// earlier one for the same id
+    fn save_report(&self, report: &ArchivedReport) -> Result<(), String>;
+
+    /// The report kept when a presentation ended, if there is one
+    fn load_report(&self, presentation_id: &str) -> Result<Option<ArchivedReport>, String>;
+
+    /// Make sure snapshots can still be written
+    fn check(&self) -> Result<(), String>;
+}
+
+/// Load every saved presentation into the presentations map. Snapshots that
+/// can't be restored are logged and skipped so one bad file doesn't stop
+/// the server from starting. Returns when each restored snapshot was taken.
+pub fn restore_presentations(
+    storage: &dyn Storage,
+    presentations: &Presentations,
+) -> HashMap<String, u64> {
+    let mut taken_at = HashMap::new();
+    let snapshots = match storage.load_all() {
+        Ok(snapshots) => snapshots,
+        Err(e) => {
+            error!("Could not load saved presentations: {e}");
+            return taken_at;
+        }
+    };
+
+    for snapshot in snapshots {
+        let id = snapshot.id.clone();
+        let snapshot_taken_at = snapshot.taken_at;
+        match Presentation::restore(snapshot) {
+            Ok(presentation) => {
+                info!("Restored presentation [{id}]");
+                presentations.insert(id.clone(), presentation);
+                taken_at.insert(id, snapshot_taken_at);
+            }
+            Err(e) => error!("Could not restore presentation [{id}]: {e}"),
+        }
+    }
+    taken_at
+}
+
+/// Save a snapshot of every presentation
+pub async fn save_presentations(storage: &SharedStorage, presentations: &Presentations) {
+    // Clone the presentations out first so we don't hold map locks across awaits
+    let all: Vec<Presentation> = presentations.iter().map(|x| x.value().clone()).collect();
+    for presentation in all {
+        save_presentation(storage, &presentation).await;
+    }
+}
+
+pub async fn save_presentation(storage: &SharedStorage, presentation: &Presentation) {
+    if presentation.is_ended() {
+        return;
+    }
+
+    // Writing files blocks so it's kept off the async workers
+    let snapshot = presentation.snapshot().await;
+    let saving = storage.clone();
+    let saved = tokio::task::spawn_blocking(move || saving.save(&snapshot))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()));
+    if let Err(e) = saved {
+        error!("Could not save presentation [{}]: {e}", presentation.id);
+    }
+
+    // If it was ended while this was being saved, its snapshot may have been
+    // removed before this one was written. Remove it again so it doesn't come
+    // back after a restart.
+    if presentation.is_ended() {
+        let removing = storage.clone();
+        let presentation_id = presentation.id.clone();
+        let removed = tokio::task::spawn_blocking(move || removing.remove(&presentation_id))
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()));
+        if let Err(e) = removed {
+            error!("Could not remove the snapshot of [{}]: {e}", presentation.id);
+        }
+    }
+}
+
+/// Periodically save every presentation for as long as the server runs
+pub fn spawn_snapshot_task(storage: SharedStorage, presentations: Presentations, interval: u64) {
+    tokio::task::spawn(async move {
+        // A zero interval would panic in here and quietly stop all saving
+        let mut timer = tokio::time::interval(Duration::from_secs(interval.max(1)));
+        loop {
+            timer.tick().await;
+            trace!("Saving snapshots of {} presentations", presentations.len());
+            save_presentations(&storage, &presentations).await;
+        }
+    });
+}
@@ -1,14 +1,70 @@
-use crate::{processor, IncomingMessage, OutgoingUserMessage, Presentation, Presenter, User};
-use futures::{stream::SplitStream, FutureExt, StreamExt};
+use crate::{config::HeartbeatConfiguration, Delivery, heartbeat::{ConnectionQuality, Heartbeat}, metrics, processor, Disconnection, IncomingMessage, OutgoingPresenterMessage, OutgoingUserMessage, Presentation, Presenter, User};
+use futures::{stream::{SplitSink, SplitStream}, SinkExt, StreamExt};
 use tokio::sync::mpsc::{self, UnboundedReceiver};
-use tokio_stream::wrappers::UnboundedReceiverStream;
 use warp::ws::{Message, WebSocket};
 
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+/// How long a closed connection has to write out what's left in its queue
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Websockets that haven't finished closing yet
+static OPEN_SOCKETS: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts a socket as open until it is dropped
+struct OpenSocket;
+
+impl OpenSocket {
+    fn new() -> Self {
+        OPEN_SOCKETS.fetch_add(1, Ordering::SeqCst);
+        Self
+    }
+}
+
+impl Drop for OpenSocket {
+    fn drop(&mut self) {
+        OPEN_SOCKETS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// How many websockets are still open, including ones still flushing
+/// their last messages
+pub fn open_sockets() -> usize {
+    OPEN_SOCKETS.load(Ordering::SeqCst)
+}
+
+/// Write everything queued for a client to its socket. Once a write fails nothing
+/// else queued can be delivered either, so all of it is counted as failed.
+async fn write_queued(
+    mut queued: mpsc::Receiver<Message>,
+    mut socket: SplitSink<WebSocket, Message>,
+    presentation_id: String,
+    _open_socket: OpenSocket,
+) {
+    while let Some(message) = queued.recv().await {
+        if let Err(e) = socket.send(message).await {
+            error!("error sending websocket msg: {}", e);
+            metrics::send_failed(&presentation_id);
+
+            // Anything queued from here on fails in `Client::queue`
+            queued.close();
+            while queued.try_recv().is_ok() {
+                metrics::send_failed(&presentation_id);
+            }
+            return;
+        }
+    }
+}
+
 async fn handle_presenter_messages(
     presenter: Presenter,
     presentation: Presentation,
     mut client_ws_rcv: SplitStream<WebSocket>,
-    mut closer_rcv: UnboundedReceiver<()>,
+    mut closer_rcv: UnboundedReceiver<Disconnection>,
+    mut heartbeat: Heartbeat,
 ) {
     let guid = &presenter.guid;
     let identity = &presenter.identity;
@@ -23,20 +79,34 @@ async fn handle_presenter_messages(
                         if msg.is_close() {
                             break;
                         }
+                        // Pings are answered automatically, pongs are for our heartbeat
+                        if msg.is_pong() {
+                            heartbeat.pong(&presenter, msg.as_bytes());
+                            continue;
+                        }
+                        if msg.is_ping() {
+                            continue;
+                        }
                         let message = match msg.to_str().map(serde_json::from_str::<IncomingMessage>) {
                             Ok(Ok(m)) => m,
                             Ok(Err(e)) => {
+                                metrics::message_received(&presentation.id, "invalid");
                                 error!("A presenter sent an invalid message: {e}");
                                 continue;
                             }
                             Err(_) => {
+                                metrics::message_received(&presentation.id, "invalid");
                                 error!("A preesnter sent a message which wasn't text!");
                                 continue;
                             }
                         };
                         match message {
-                            IncomingMessage::Presenter(presenter_message) => processor::handle_presenter_message_types(presenter_message, presenter.clone(), presentation.clone()).await,
+                            IncomingMessage::Presenter(presenter_message) => {
+                                metrics::message_received(&presentation.id, presenter_message.kind());
+                                processor::handle_presenter_message_types(presenter_message, presenter.clone(), presentation.clone()).await
+                            }
                             _ => {
+                                metrics::message_received(&presentation.id, "invalid");
                                 warn!("{identity} sent a valid message but it was not a presenter message");
                                 continue;
                             }
@@ -53,22 +123,43 @@ async fn handle_presenter_messages(
                     }
                 };
             }
-            _ = closer_rcv.recv() => {
-                info!("{identity} - is switching to a new device for {}", presentation.id);
+            reason = closer_rcv.recv() => {
+                info!("{identity} - is being disconnected from {}", presentation.id);
+                let reason = reason.unwrap_or_default();
+                if reason.evicted {
+                    warn!("{identity} is too far behind in [{}], disconnecting them", presentation.id);
+                    metrics::client_evicted(&presentation.id);
+                }
                 // Inform the presenter the connection is being close
-                //presenter.send_ignore_fail(OutgoingUserMessage::Disconnected(String::new()));
+                presenter.send_ignore_fail(OutgoingPresenterMessage::Disconnect {
+                    reason: reason.reason,
+                    reconnect: reason.reconnect,
+                });
                 break;
             }
+            _ = heartbeat.tick() => {
+                if !heartbeat.beat(&presenter) {
+                    warn!("{identity} stopped answering pings, dropping their connection");
+                    break;
+                }
+                presenter.send_ignore_fail(OutgoingPresenterMessage::ConnectionQuality(
+                    ConnectionQuality::measure(&presentation, guid),
+                ));
+            }
         }
     }
     warn!("Done handling presenter messages for: [{identity}] on [{guid}]");
+
+    // Forget the connection so the presentation doesn't look like it still has a presenter
+    presentation.presenters.remove(guid);
 }
 
 async fn handle_user_messages(
     user: User,
     presentation: Presentation,
     mut client_ws_rcv: SplitStream<WebSocket>,
-    mut closer_rcv: UnboundedReceiver<()>,
+    mut closer_rcv: UnboundedReceiver<Disconnection>,
+    mut heartbeat: Heartbeat,
 ) {
     let guid = &user.guid;
     let identity = &user.identity;
@@ -84,17 +175,30 @@ async fn handle_user_messages(
                         if msg.is_close() {
                             break;
                         }
+                        // Pings are answered automatically, pongs are for our heartbeat
+                        if msg.is_pong() {
+                            heartbeat.pong(&user, msg.as_bytes());
+                            continue;
+                        }
+                        if msg.is_ping() {
+                            continue;
+                        }
 
                         let message = match msg.to_str().map(serde_json::from_str::<IncomingMessage>) {
                             Ok(Ok(m)) => m,
                             _ => {
+                                metrics::message_received(&presentation.id, "invalid");
                                 error!("{identity} sent an invalid message");
                                 continue;
                             }
                         };
                         match message {
-                            IncomingMessage::User(user_message) => processor::handle_user_message_types(user_message, user.clone(), presentation.clone()).await,
+                            IncomingMessage::User(user_message) => {
+                                metrics::message_received(&presentation.id, user_message.kind());
+                                processor::handle_user_message_types(user_message, user.clone(), presentation.clone()).await
+                            }
                             _ => {
+                                metrics::message_received(&presentation.id, "invalid");
                                 error!("{identity} sent an invalid message");
                                 continue;
                             }
@@ -111,12 +215,26 @@ async fn handle_user_messages(
                     }
                 };
             }
-            _ = closer_rcv.recv() => {
-                info!("{identity} - is switching to a new device for {}", presentation.id);
+            reason = closer_rcv.recv() => {
+                info!("{identity} - is being disconnected from {}", presentation.id);
+                let reason = reason.unwrap_or_default();
+                if reason.evicted {
+                    warn!("{identity} is too far behind in [{}], disconnecting them", presentation.id);
+                    metrics::client_evicted(&presentation.id);
+                }
                 // Internal request to close the connection
-                user.send_ignore_fail(OutgoingUserMessage::Disconnect(String::new()));
+                user.send_ignore_fail(OutgoingUserMessage::Disconnect {
+                    reason: reason.reason,
+                    reconnect: reason.reconnect,
+                });
                 break;
             }
+            _ = heartbeat.tick() => {
+                if !heartbeat.beat(&user) {
+                    warn!("{identity} stopped answering pings, dropping their connection");
+                    break;
+                }
+            }
         }
     }
 
@@ -125,6 +243,7 @@ async fn handle_user_messages(
     //
     // If we initiated the close, the user will already be disconnected.
     if presentation.users.remove(&user) {
+        presentation.update_audience();
         info!("{identity} - {guid} disconnected from {}", presentation.id);
     } else {
         warn!(
@@ -134,25 +253,33 @@ async fn handle_user_messages(
     }
 }
 
-pub async fn new_connection(ws: WebSocket, presentation: Presentation, guid: String) {
+pub async fn new_connection(
+    ws: WebSocket,
+    presentation: Presentation,
+    guid: String,
+    heartbeat: HeartbeatConfiguration,
+    queue_depth: usize,
+) {
     // Take the web socket and split it into a sender and receiver. The sender and receiver here
     // are not directly connected. The sender sends messages to the client, and receiver receives
     // responses which may or may not be related to those messages.
     let (client_ws_sender, client_ws_rcv) = ws.split();
 
     // Create a channel to send messages to the client that is easier to pass around without polluting
-    // the entire codebase with websocket types.
-    let (client_sender, client_rcv) = mpsc::unbounded_channel();
+    // the entire codebase with websocket types. It's bounded so a client that stops reading can't
+    // make us hold on to more and more messages.
+    let (client_sender, client_rcv) = mpsc::channel(queue_depth.max(1));
 
     // Create an internal messaging channel to close the connection when we drop the client
-    let (closer, closer_rcv) = mpsc::unbounded_channel::<()>();
+    let (closer, closer_rcv) = mpsc::unbounded_channel::<Disconnection>();
 
-    let client_rcv = UnboundedReceiverStream::new(client_rcv);
-    tokio::task::spawn(client_rcv.forward(client_ws_sender).map(|result| {
-        if let Err(e) = result {
-            error!("error sending websocket msg: {}", e);
-        }
-    }));
+    // The socket only counts as closed once everything queued for it has been written
+    let mut writer = tokio::task::spawn(write_queued(
+        client_rcv,
+        client_ws_sender,
+        presentation.id.clone(),
+        OpenSocket::new(),
+    ));
 
     let is_presenter = presentation.presenters.contains_key(&guid);
 
@@ -166,13 +293,20 @@ pub async fn new_connection(ws: WebSocket, presentation: Presentation, guid: Str
         };
 
         // Add the channels to complete the connection
-        presenter.sender = Some(client_sender.clone());
+        presenter.sender = Some(client_sender);
         presenter.closer = Some(closer);
         presentation
             .presenters
             .insert(guid.clone(), presenter.clone());
 
-        handle_presenter_messages(presenter, presentation, client_ws_rcv, closer_rcv).await;
+        handle_presenter_messages(
+            presenter,
+            presentation,
+            client_ws_rcv,
+            closer_rcv,
+            Heartbeat::new(&heartbeat),
+        )
+        .await;
 
         warn!("A presenter connection has just closed!");
     } else {
@@ -185,22 +319,48 @@ pub async fn new_connection(ws: WebSocket, presentation: Presentation, guid: Str
         };
 
         // Add the channels to complete the connection
-        user.sender = Some(client_sender.clone());
+        user.sender = Some(client_sender);
         user.closer = Some(closer);
-        presentation.users.insert(user.clone());
 
-        // Send the initial presentation data including the current slide data
-        let _ = client_sender.send(Ok(Message::text(
-            OutgoingUserMessage::InitialPresentationData {
+        let resume_tokens = presentation.get_resume_tokens();
+        let resume_token = resume_tokens.issue(&user.identity);
+        let settings = presentation.slide_settings.read().await.clone();
+
+        // Send the initial presentation data including the current slide data, then
+        // anything a resuming client missed. The user only starts getting new updates
+        // once they're added, so nothing can arrive out of order.
+        presentation.get_updates().catch_up(user.resume_from, |seq, missed| {
+            user.send_ignore_fail(OutgoingUserMessage::InitialPresentationData {
                 title: presentation.get_title(),
-                settings: presentation.slide_settings.read().await.clone(),
+                settings,
+                resume_token: resume_token.clone(),
+                seq,
+            });
+            for event in missed {
+                user.queue(Message::text(event), Delivery::Required);
             }
-            .json(),
-        )));
+            presentation.users.insert(user.clone());
+        });
+        presentation.update_audience();
 
         let identity = user.identity.clone();
-        handle_user_messages(user, presentation, client_ws_rcv, closer_rcv).await;
+        handle_user_messages(
+            user,
+            presentation,
+            client_ws_rcv,
+            closer_rcv,
+            Heartbeat::new(&heartbeat),
+        )
+        .await;
+        resume_tokens.connection_closed(&resume_token);
 
         info!("User connection for [{identity}] has finished");
     }
+
+    // Give the socket a moment to take whatever is left in its queue, like the reason
+    // it was disconnected. One that has stopped reading won't, so don't wait forever.
+    if tokio::time::timeout(FLUSH_TIMEOUT, &mut writer).await.is_err() {
+        warn!("Gave up flushing a websocket that isn't reading");
+        writer.abort();
+    }
 }
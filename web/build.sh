@@ -7,6 +7,7 @@ cp -r ./styles/* ../webroot
 cp ./join.html ../webroot/join.html
 cp ./new.html ../webroot/new.html
 cp ./present.html ../webroot/present.html
+cp ./encryption.js ../webroot/encryption.js
 
 # Begin build elm source files
 cd ./elm
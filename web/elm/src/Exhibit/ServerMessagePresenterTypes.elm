@@ -7,7 +7,7 @@ import Dict exposing (Dict)
 
 type ReceivedMessage
     = Emoji EmojiMessage
-    | PollResults(Dict String Int)
+    | PollResults PollTotals
     | Error String
 
 type alias EmojiMessage =
@@ -16,6 +16,89 @@ type alias EmojiMessage =
     }
 
 
+-- Live or final results for a poll. This needs to mirror the rust type
+-- variation OutgoingPresenterMessage::PollResults
+
+
+type alias PollTotals =
+    { name : String
+    , totals : Dict String ChoiceTotals
+    , runoff : Maybe RunoffResults
+    }
+
+
+type alias ChoiceTotals =
+    { sum : Int
+    , count : Int
+    , min : Int
+    , max : Int
+    , mean : Float
+    }
+
+
+type alias RunoffRound =
+    { counts : Dict String Int
+    , exhausted : Int
+    , eliminated : List String
+    }
+
+
+type alias RunoffResults =
+    { rounds : List RunoffRound
+    , winner : Maybe String
+    , tied : List String
+    }
+
+
+-- The number to show for each option. Ranked polls show the last round of
+-- their instant-runoff, other polls the sum of everyone's votes, which for
+-- yes or no votes is how many people picked the option.
+
+
+pollResultCounts : PollTotals -> Dict String Int
+pollResultCounts results =
+    case results.runoff |> Maybe.andThen (.rounds >> List.reverse >> List.head) of
+        Just lastRound ->
+            lastRound.counts
+
+        Nothing ->
+            Dict.map (\_ totals -> totals.sum) results.totals
+
+
+pollTotalsDecoder : Decoder PollTotals
+pollTotalsDecoder =
+    Json.Decode.map3 PollTotals
+        (Json.Decode.field "name" Json.Decode.string)
+        (Json.Decode.field "totals" (Json.Decode.dict choiceTotalsDecoder))
+        (Json.Decode.maybe (Json.Decode.field "runoff" runoffResultsDecoder))
+
+
+choiceTotalsDecoder : Decoder ChoiceTotals
+choiceTotalsDecoder =
+    Json.Decode.map5 ChoiceTotals
+        (Json.Decode.field "sum" Json.Decode.int)
+        (Json.Decode.field "count" Json.Decode.int)
+        (Json.Decode.field "min" Json.Decode.int)
+        (Json.Decode.field "max" Json.Decode.int)
+        (Json.Decode.field "mean" Json.Decode.float)
+
+
+runoffResultsDecoder : Decoder RunoffResults
+runoffResultsDecoder =
+    Json.Decode.map3 RunoffResults
+        (Json.Decode.field "rounds" (Json.Decode.list runoffRoundDecoder))
+        (Json.Decode.field "winner" (Json.Decode.nullable Json.Decode.string))
+        (Json.Decode.field "tied" (Json.Decode.list Json.Decode.string))
+
+
+runoffRoundDecoder : Decoder RunoffRound
+runoffRoundDecoder =
+    Json.Decode.map3 RunoffRound
+        (Json.Decode.field "counts" (Json.Decode.dict Json.Decode.int))
+        (Json.Decode.field "exhausted" Json.Decode.int)
+        (Json.Decode.field "eliminated" (Json.Decode.list Json.Decode.string))
+
+
 emojiMessageDecoder : Decoder EmojiMessage
 emojiMessageDecoder =
     Json.Decode.map2 EmojiMessage
@@ -27,6 +110,6 @@ receivedWebsocketMessageDecoder : Decoder ReceivedMessage
 receivedWebsocketMessageDecoder =
     Json.Decode.oneOf
         [ Json.Decode.map Emoji (nestWebsocketMessageDecoder "Emoji" emojiMessageDecoder)
-        , Json.Decode.map PollResults (nestWebsocketMessageDecoder "PollResults" (Json.Decode.dict Json.Decode.int))
+        , Json.Decode.map PollResults (nestWebsocketMessageDecoder "PollResults" pollTotalsDecoder)
         , Json.Decode.map Error (nestWebsocketMessageDecoder "Error" Json.Decode.string)
         ]
\ No newline at end of file
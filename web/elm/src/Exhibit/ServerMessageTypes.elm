@@ -1,7 +1,7 @@
 module Exhibit.ServerMessageTypes exposing (..)
 
 import Dict exposing (Dict)
-import Json.Decode exposing (Decoder, andThen, fail, field, map2, string, succeed)
+import Json.Decode exposing (Decoder, andThen, bool, fail, field, map2, string, succeed)
 import Exhibit.IO exposing (Poll, pollDecoder, nestWebsocketMessageDecoder)
 
 
@@ -16,7 +16,7 @@ type SuccessType
 type ReceivedMessage
     = NewSlideMessage SlideSettings
     | InitialPresentationDataMessage InitialPresentationData
-    | DisconnectMessage String
+    | DisconnectMessage Disconnection
     | RatelimiterResponseMessage RatelimiterResponse
     | NewPollMessage Poll
     | Success SuccessType
@@ -40,6 +40,14 @@ type RatelimiterResponse
     | Blocked String
 
 
+-- Sent just before the server closes the socket. This needs to mirror
+-- the rust type variation OutgoingUserMessage::Disconnect
+
+
+type alias Disconnection =
+    { reason : String, reconnect : Bool }
+
+
 type alias InitialPresentationData =
     { title : String, settings : Maybe SlideSettings }
 
@@ -53,7 +61,7 @@ receivedWebsocketMessageDecoder =
     Json.Decode.oneOf
         [ Json.Decode.map NewSlideMessage newSlideMessageDecoder
         , Json.Decode.map InitialPresentationDataMessage initialPresentationDataMessageDecoder
-        , Json.Decode.map DisconnectMessage (simpleMessageDecoder "Disconnect")
+        , Json.Decode.map DisconnectMessage disconnectMessageDecoder
         , Json.Decode.map RatelimiterResponseMessage ratelimiterResponseMessageDecoder
         , Json.Decode.map NewPollMessage newPollMessageDecoder
         , Json.Decode.map Success successMessageDecoder
@@ -63,6 +71,11 @@ receivedWebsocketMessageDecoder =
 
 
 
+disconnectMessageDecoder : Decoder Disconnection
+disconnectMessageDecoder =
+    field "Disconnect" (map2 Disconnection (field "reason" string) (field "reconnect" bool))
+
+
 successMessageDecoder : Decoder SuccessType
 successMessageDecoder =
     field "Success" string
@@ -48,6 +48,7 @@ type PollState
 type State
     = Disconnected
     | Reconnecting
+    | Ended String
     | Joining
     | Viewing InputView
 
@@ -136,19 +137,30 @@ update msg model =
         -- On the websocket being disconnected, we need to update the UI
         -- to tell the user this so they can decide what they want to do.
         SocketDisconnected _ ->
-            ( { model | state = Reconnecting, title = "Disconnected From Server" }, Cmd.none )
+            case model.state of
+                Ended _ ->
+                    ( model, Cmd.none )
+
+                _ ->
+                    ( { model | state = Reconnecting, title = "Disconnected From Server" }, Cmd.none )
         
         -- Refresh socket has some duplicated logic from the above AuthenticateToPresentation and GotResponse
         -- These are split to achieve silent reconnect
+        -- Once the presentation has ended there's nothing to reconnect to
         RefreshSocket _ ->
-            let de = Debug.log "Attempting AuthenticateToPresentation" in
-            ( model
-            , Http.post
-                { url = "/join"
-                , body = Http.stringBody "application/text" model.registration_key
-                , expect = Http.expectJson GotWebsocketAddressSilentUpdate joinPresentationResponseDecoder
-                }
-            )
+            case model.state of
+                Ended _ ->
+                    ( model, Cmd.none )
+
+                _ ->
+                    let de = Debug.log "Attempting AuthenticateToPresentation" in
+                    ( model
+                    , Http.post
+                        { url = "/join"
+                        , body = Http.stringBody "application/text" model.registration_key
+                        , expect = Http.expectJson GotWebsocketAddressSilentUpdate joinPresentationResponseDecoder
+                        }
+                    )
         GotWebsocketAddressSilentUpdate response ->
             case response of
                 Ok joinPresentationResponse ->
@@ -169,8 +181,12 @@ update msg model =
                 Ok (NewSlideMessage slideSettings) ->
                     update (NewSlideEvent slideSettings) model
                   
-                Ok (DisconnectMessage m) ->
-                    update (SocketDisconnected m) model
+                Ok (DisconnectMessage disconnection) ->
+                    if disconnection.reconnect then
+                        update (SocketDisconnected disconnection.reason) model
+
+                    else
+                        ( { model | state = Ended disconnection.reason, title = "Presentation Ended" }, Cmd.none )
                 
                 Ok(RatelimiterResponseMessage m) ->
                     ({model | response = Just m}, Cmd.none)
@@ -279,6 +295,10 @@ view model =
                 Reconnecting -> 
                     -- On websocket disconnect, show reconnecting state 
                     viewReconnectingState model
+
+                Ended reason ->
+                    -- The presenter ended the presentation or it went idle
+                    viewEndedState reason
                     
                 Viewing inputView ->
                     div [] [
@@ -307,6 +327,21 @@ viewDisconnectedState _ =
         ]
     ]
 
+viewEndedState : String -> Html Msg
+viewEndedState reason =
+    div [ class "container" ] [
+        div [ class "container-type-row"] [
+            span [class "container-type-icon"] [ img [src "/static/icons/disconnected.png"] [] ] 
+            , span [class "container-type-text"] [text "Presentation ended"]
+        ]
+        , div [ class "container-title-row"] [
+            span [class "container-title-text"] [text "This presentation is over"]
+        ]
+        , div [ class "container-paragraph-row"] [
+            span [class "container-paragraph-text"] [text reason]
+        ]
+    ]
+
 viewJoiningState : Model -> Html Msg
 viewJoiningState _ =
     div [ class "container" ] [
@@ -14,7 +14,7 @@ import Json.Decode as Decode exposing (field, string, int)
 import Task exposing (..)
 import Html.Attributes exposing (src)
 import Json.Encode
-import Exhibit.ServerMessagePresenterTypes exposing (receivedWebsocketMessageDecoder, ReceivedMessage(..))
+import Exhibit.ServerMessagePresenterTypes exposing (receivedWebsocketMessageDecoder, ReceivedMessage(..), PollTotals, pollResultCounts)
 import Json.Decode exposing (errorToString)
 import Exhibit.UserMessageTypes exposing (encodeVoteType)
 import Process
@@ -214,7 +214,7 @@ type alias Model =
     , resolvedSlideHistory: List Int
     , currentSlideIndex : Int
     , state : State
-    , currentPollResults : Dict String Int
+    , currentPollResults : Maybe PollTotals
     , currentPollRender : Maybe PollRender
     , allPollResults : Dict String Int -- Lookup of poll name to index of winning option
     , killswitch_count : Int
@@ -259,7 +259,7 @@ init registration_key =
       , currentSlide = Nothing
       , resolvedSlideHistory = []
       , state = Disconnected
-      , currentPollResults = Dict.empty
+      , currentPollResults = Nothing
       , currentPollRender = Nothing
       , allPollResults = Dict.empty
       , currentSlideIndex = 0
@@ -356,7 +356,7 @@ update msg model =
                         (model, addAnimatedEmoji (emoji_msg.emoji, emoji_msg.size))
                     Ok (PollResults currentPollResults) -> 
                         -- let _ = Debug.log "Poll Results" currentPollResults in
-                        ( {model | currentPollResults = currentPollResults}, Cmd.none)
+                        ( {model | currentPollResults = Just currentPollResults}, Cmd.none)
                     Ok (Error e) -> ({model | status = Just e}, Cmd.none)
                     Err e -> ({model | status = Just (errorToString e)}, Cmd.none)
 
@@ -451,7 +451,7 @@ update msg model =
 computeNextSlide : Model -> Slide -> (Model, Cmd Msg)
 computeNextSlide model currentSlide = 
     let
-        -- From the server we get a list of (pollLabel, count) as model.currentPollResults
+        -- From the server we get the totals for each pollLabel, currentPollCounts turns them into a count each
         --
         -- We know the current slide's poll options as list
         -- ex.  Current poll:               [ "A", "B", "C" ]
@@ -488,7 +488,7 @@ computeNextSlide model currentSlide =
                         |> List.head
             in
                 currentSlide.data.poll
-                    |> Maybe.andThen (\currentPoll -> findWinningPollOptionIndex currentPoll model.currentPollResults) 
+                    |> Maybe.andThen (\currentPoll -> findWinningPollOptionIndex currentPoll (currentPollCounts model.currentPollResults)) 
                     |> Maybe.withDefault (0, "")
 
         -- _ = Debug.log "highestVotedOptionIndexOfCurrentSlidePoll is " highestVotedOptionIndexOfCurrentSlidePoll
@@ -560,7 +560,7 @@ computeNextSlide model currentSlide =
         updatedModel = 
             { model
                 | currentSlide = nextSlide 
-                , currentPollResults = Dict.empty
+                , currentPollResults = Nothing
                 , currentPollRender = maybeNewSlidePollRender
                 , allPollResults = newAllPollResults
                 , resolvedSlideHistory = updatedSlideHistoryIndices
@@ -744,12 +744,20 @@ view model =
 
 
 
-renderBarGraph : Dict String Int -> PollRender -> Html Msg
+-- The number to show for each option of the current poll, if there are any results yet
+currentPollCounts : Maybe PollTotals -> Dict String Int
+currentPollCounts currentPollResults =
+    currentPollResults
+        |> Maybe.map pollResultCounts
+        |> Maybe.withDefault Dict.empty
+
+
+renderBarGraph : Maybe PollTotals -> PollRender -> Html Msg
 renderBarGraph currentPollResults render = 
     let
         sortedOptions : List (Int, String, Int)
         sortedOptions = 
-            currentPollResults
+            currentPollCounts currentPollResults
                 |> Dict.toList
                 |> List.indexedMap (\index (key, val) -> (index, key, val) )
                 |> List.sortBy (\(_, _, val) -> val) 
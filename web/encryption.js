@@ -0,0 +1,229 @@
+// Sealing for encrypted presentations, shared by the join and present pages.
+//
+// The key comes from the fragment of the link (#key=...), which browsers never
+// send to the server, and is kept in session storage so refreshing the page
+// still works. Everything written in the presentation is sealed with
+// AES-256-GCM as v1.<nonce>.<ciphertext>, both parts unpadded URL safe base64.
+//
+// The Elm applications only ever see plaintext. Messages are sealed on their
+// way to the socket and opened on their way back. Without a key messages pass
+// through untouched.
+
+// Shown in place of content that can't be opened with the key we have
+const LOCKED = "🔒";
+
+// A 12 byte nonce and at least the 16 byte tag
+const SEALED = /^v1\.[A-Za-z0-9_-]{16}\.[A-Za-z0-9_-]{22,}$/;
+
+// Presenter messages that name a poll by its (sealed) name
+const POLL_COMMANDS = [
+    "GetPollTotals", "SubscribePollTotals", "UnsubscribePollTotals",
+    "ClosePoll", "ReopenPoll", "DeletePoll", "RevealQuizAnswer",
+];
+
+function toBase64url(bytes) {
+    return btoa(String.fromCharCode(...new Uint8Array(bytes)))
+        .replace(/\+/g, "-").replace(/\//g, "_").replace(/=+$/, "");
+}
+
+function fromBase64url(text) {
+    return Uint8Array.from(atob(text.replace(/-/g, "+").replace(/_/g, "/")), c => c.charCodeAt(0));
+}
+
+class Sealer {
+    // Read the key before anything rewrites the URL. storageName keeps the
+    // presenter's and the audience's keys apart.
+    constructor(storageName) {
+        const fragment = new URLSearchParams(window.location.hash.slice(1));
+        let encoded = fragment.get("key");
+        if (encoded) {
+            sessionStorage.setItem(storageName, encoded);
+        } else {
+            encoded = sessionStorage.getItem(storageName);
+        }
+
+        this.key = encoded
+            ? crypto.subtle.importKey("raw", fromBase64url(encoded), "AES-GCM", false, ["encrypt", "decrypt"])
+                .catch((e) => {
+                    console.log("Could not use the encryption key", e);
+                    return null;
+                })
+            : Promise.resolve(null);
+
+        // Votes and presenter commands have to name polls and options exactly
+        // as they were first sealed, so remember them by their plaintext:
+        // name -> { name: sealed name, options: Map(option -> sealed option) }
+        this.polls = new Map();
+
+        // Messages are handled one at a time in each direction so they stay in order
+        this.sending = Promise.resolve();
+        this.receiving = Promise.resolve();
+    }
+
+    // Seal and pass an outgoing message to send, in the order they were given
+    send(message, send) {
+        this.sending = this.sending
+            .then(() => this.sealMessage(message))
+            .then(send, (e) => console.log("Could not seal message", e));
+    }
+
+    // Open and pass an incoming message to receive, in the order they arrived
+    receive(message, receive) {
+        this.receiving = this.receiving
+            .then(() => this.openMessage(message))
+            .then(receive, (e) => console.log("Could not open message", e));
+    }
+
+    async seal(text) {
+        const key = await this.key;
+        if (!key) {
+            return text;
+        }
+        const nonce = crypto.getRandomValues(new Uint8Array(12));
+        const sealed = await crypto.subtle.encrypt({ name: "AES-GCM", iv: nonce }, key, new TextEncoder().encode(text));
+        return `v1.${toBase64url(nonce)}.${toBase64url(sealed)}`;
+    }
+
+    async unseal(text) {
+        if (!SEALED.test(text)) {
+            return text;
+        }
+        const key = await this.key;
+        if (!key) {
+            return LOCKED;
+        }
+        const [, nonce, ciphertext] = text.split(".");
+        try {
+            const plain = await crypto.subtle.decrypt({ name: "AES-GCM", iv: fromBase64url(nonce) }, key, fromBase64url(ciphertext));
+            return new TextDecoder().decode(plain);
+        } catch (e) {
+            return LOCKED;
+        }
+    }
+
+    // Seal a poll the presenter is sending, reusing what was sealed before
+    // if the poll is sent again
+    async sealPoll(poll) {
+        let known = this.polls.get(poll.name);
+        if (!known) {
+            known = { name: await this.seal(poll.name), options: new Map() };
+            this.polls.set(poll.name, known);
+        }
+        for (const option of poll.options) {
+            if (!known.options.has(option)) {
+                known.options.set(option, await this.seal(option));
+            }
+        }
+        return { ...poll, name: known.name, options: poll.options.map(option => known.options.get(option)) };
+    }
+
+    sealedPollName(name) {
+        const known = this.polls.get(name);
+        return known ? known.name : name;
+    }
+
+    sealedOption(pollName, option) {
+        const known = this.polls.get(pollName);
+        return (known && known.options.get(option)) || option;
+    }
+
+    sealVote(pollName, voteType) {
+        const option = (choice) => this.sealedOption(pollName, choice);
+        const [[kind, vote]] = Object.entries(voteType);
+        const sealed = { ...vote };
+        if ("choice" in vote) {
+            sealed.choice = option(vote.choice);
+        }
+        if ("choices" in vote) {
+            sealed.choices = Object.fromEntries(Object.entries(vote.choices).map(([choice, value]) => [option(choice), value]));
+        }
+        if ("ranking" in vote) {
+            sealed.ranking = vote.ranking.map(option);
+        }
+        return { [kind]: sealed };
+    }
+
+    async sealMessage(message) {
+        if (!(await this.key)) {
+            return message;
+        }
+        let parsed;
+        try {
+            parsed = JSON.parse(message);
+        } catch (e) {
+            // Pings and the hello aren't JSON
+            return message;
+        }
+
+        const presenter = parsed.Presenter;
+        if (presenter && presenter.NewSlide) {
+            const settings = presenter.NewSlide.slide_settings;
+            settings.message = await this.seal(settings.message);
+        } else if (presenter && presenter.NewPoll) {
+            presenter.NewPoll = await this.sealPoll(presenter.NewPoll);
+        } else if (presenter && presenter.NewPrompt) {
+            presenter.NewPrompt.question = await this.seal(presenter.NewPrompt.question);
+        } else if (presenter) {
+            for (const command of POLL_COMMANDS) {
+                if (presenter[command]) {
+                    presenter[command].name = this.sealedPollName(presenter[command].name);
+                }
+            }
+        }
+
+        const user = parsed.User;
+        if (user && user.TextResponse) {
+            user.TextResponse.text = await this.seal(user.TextResponse.text.trim());
+        } else if (user && user.Question) {
+            user.Question.text = await this.seal(user.Question.text.trim());
+        } else if (user && user.Vote) {
+            const name = user.Vote.poll_name;
+            user.Vote.vote_type = this.sealVote(name, user.Vote.vote_type);
+            user.Vote.poll_name = this.sealedPollName(name);
+        } else if (user && user.RetractVote) {
+            user.RetractVote.poll_name = this.sealedPollName(user.RetractVote.poll_name);
+        }
+
+        return JSON.stringify(parsed);
+    }
+
+    async openMessage(message) {
+        if (!message.includes('"v1.')) {
+            return message;
+        }
+        let parsed;
+        try {
+            parsed = JSON.parse(message);
+        } catch (e) {
+            return message;
+        }
+        return JSON.stringify(await this.open(parsed));
+    }
+
+    // Open every sealed string in a value, object keys included since poll
+    // totals are keyed by option
+    async open(value) {
+        if (typeof value === "string") {
+            return this.unseal(value);
+        }
+        if (Array.isArray(value)) {
+            return Promise.all(value.map(x => this.open(x)));
+        }
+        if (value === null || typeof value !== "object") {
+            return value;
+        }
+
+        const opened = {};
+        for (const [key, inner] of Object.entries(value)) {
+            opened[await this.unseal(key)] = await this.open(inner);
+        }
+        // Remember polls as they arrive so votes can name their sealed options
+        if (typeof value.name === "string" && Array.isArray(value.options)) {
+            this.polls.set(opened.name, {
+                name: value.name,
+                options: new Map(value.options.map((option, i) => [opened.options[i], option])),
+            });
+        }
+        return opened;
+    }
+}